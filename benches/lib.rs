@@ -121,30 +121,59 @@ impl TestHttpCallback {
 }
 
 impl <'r> HttpRequestCallbacks<'r> for TestHttpCallback {
-    fn on_request_line(&mut self, parser: &mut HttpParser, request: RequestLine) {
+    fn on_request_line(&mut self, parser: &mut HttpParser, request: RequestLine)
+    -> CallbackResult<()> {
         // self.method = String::from_utf8(request.method.to_owned()).unwrap();
         // self.path = String::from_utf8(request.path.to_owned()).unwrap();
         // self.version = (
         //     util::dec_buf_to_int(request.version.0).unwrap(),
         //     util::dec_buf_to_int(request.version.1).unwrap(),
         // );
+        Ok(())
     }
 }
 
 impl <'r> HttpMessageCallbacks<'r> for TestHttpCallback {
-    fn on_header(&mut self, parser: &mut HttpParser, name: &[u8], value: &[u8]) {
+    fn on_message_begin(&mut self, parser: &mut HttpParser) -> CallbackResult<()> {
+        Ok(())
+    }
+
+    fn on_header(&mut self, parser: &mut HttpParser, name: &[u8], value: &[u8])
+    -> CallbackResult<()> {
         // self.headers.insert(String::from_utf8(name.to_owned()).unwrap(), String::from_utf8(value.to_owned()).unwrap());
+        Ok(())
     }
 
-    fn on_headers_finished(&mut self, parser: &mut HttpParser, body_type: BodyType) -> ExpectBody {
-        ExpectBody::Maybe
+    fn on_headers_finished(&mut self, parser: &mut HttpParser, body_type: BodyType)
+    -> CallbackResult<ExpectBody> {
+        Ok(ExpectBody::Maybe)
     }
 
-    fn on_chunk(&mut self, parser: &mut HttpParser, data: &[u8]) {
+    fn on_chunk_header(&mut self, parser: &mut HttpParser, header: ChunkHeader)
+    -> CallbackResult<()> {
+        Ok(())
+    }
+
+    fn on_chunk(&mut self, parser: &mut HttpParser, data: &[u8]) -> CallbackResult<()> {
         // self.chunks.push_str(str::from_utf8(data).unwrap());
+        Ok(())
+    }
+
+    fn on_trailer(&mut self, parser: &mut HttpParser, name: &[u8], value: &[u8])
+    -> CallbackResult<()> {
+        Ok(())
+    }
+
+    fn on_trailers_finished(&mut self, parser: &mut HttpParser) -> CallbackResult<()> {
+        Ok(())
     }
 
-    fn on_end(&mut self, parser: &mut HttpParser) {
+    fn on_end(&mut self, parser: &mut HttpParser) -> CallbackResult<()> {
         self.finished = true;
+        Ok(())
+    }
+
+    fn on_upgrade(&mut self, parser: &mut HttpParser) -> CallbackResult<()> {
+        Ok(())
     }
 }
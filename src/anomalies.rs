@@ -0,0 +1,165 @@
+//! Finds the questionable syntax that lenient parsing accepts, so it can be reported through
+//! `HttpMessageCallbacks::on_anomaly`.
+//!
+//! Each function takes a complete line that the parsers in `nom_parsers` accepted, including
+//! its line ending, and returns the position of each anomaly in the line. Strict mode rejects
+//! most of these, so in that mode only the ones it allows are ever found.
+
+use nom_parsers::{is_space, is_token_char, skip_space};
+
+
+/// Something questionable that the parser accepted.
+#[derive(PartialEq,Eq,Debug,Clone,Copy)]
+pub enum Anomaly {
+    /// A line ending without a CR.
+    BareLF,
+    /// More than a single space between the parts of a start line, a tab in place of the
+    /// space, or whitespace after the version of a request line.
+    UnexpectedWhitespace,
+    /// Whitespace between a header name and the colon.
+    WhitespaceBeforeColon,
+    /// A header value continued onto the next line.
+    ObsFold,
+    /// A Content-Length given more than once, either in separate headers or as a list. If the
+    /// values differ the header is then rejected with `ConflictingContentLength`.
+    DuplicateContentLength,
+    /// Both a Transfer-Encoding and a Content-Length, reported at whichever comes second. Unless
+    /// `ParserConfig::allow_conflicting_framing` is set the message is then rejected, and
+    /// otherwise Transfer-Encoding wins and the connection can't be reused.
+    TransferEncodingWithContentLength,
+    /// A status line without a reason phrase.
+    MissingReasonPhrase,
+}
+
+
+/// The length of the line without its line ending.
+fn content_end(line: &[u8]) -> usize {
+    match line.len() {
+        len if len >= 2 && line[len - 2] == b'\r' => len - 2,
+        len if len >= 1 && line[len - 1] == b'\n' => len - 1,
+        len => len,
+    }
+}
+
+fn bare_lfs(line: &[u8], anomalies: &mut Vec<(usize, Anomaly)>) {
+    for (idx, chr) in line.iter().enumerate() {
+        if *chr == b'\n' && (idx == 0 || line[idx - 1] != b'\r') {
+            anomalies.push((idx, Anomaly::BareLF));
+        }
+    }
+}
+
+/// Checks that the whitespace between the parts of a start line, up to `end`, is single
+/// spaces. If `trailing` is set any whitespace right before `end` is also reported.
+fn separators(line: &[u8], end: usize, trailing: bool, anomalies: &mut Vec<(usize, Anomaly)>) {
+    let mut idx = 0;
+    while idx < end {
+        if !is_space(line[idx]) {
+            idx += 1;
+            continue;
+        }
+
+        let start = idx;
+        while idx < end && is_space(line[idx]) {
+            idx += 1;
+        }
+        if (trailing && idx == end) || line[start] == b'\t' {
+            anomalies.push((start, Anomaly::UnexpectedWhitespace));
+        } else if idx - start > 1 {
+            anomalies.push((start + 1, Anomaly::UnexpectedWhitespace));
+        }
+    }
+}
+
+pub fn request_line(line: &[u8]) -> Vec<(usize, Anomaly)> {
+    let mut anomalies = Vec::new();
+    separators(line, content_end(line), true, &mut anomalies);
+    bare_lfs(line, &mut anomalies);
+    anomalies
+}
+
+pub fn response_line(line: &[u8]) -> Vec<(usize, Anomaly)> {
+    let mut anomalies = Vec::new();
+    let end = content_end(line);
+
+    // The parser has already checked that there's an HTTP/x.y version and a status code.
+    let code_start = skip_space(line, 8);
+    let phrase_start = skip_space(line, code_start + 3);
+    if phrase_start >= end {
+        separators(line, end, false, &mut anomalies);
+        anomalies.push((end, Anomaly::MissingReasonPhrase));
+    } else {
+        separators(line, phrase_start, false, &mut anomalies);
+    }
+    bare_lfs(line, &mut anomalies);
+    anomalies
+}
+
+pub fn header_line(line: &[u8]) -> Vec<(usize, Anomaly)> {
+    let mut anomalies = Vec::new();
+    let name_end = line.iter().position(|c| !is_token_char(*c)).unwrap_or(line.len());
+    if line.get(name_end).map_or(false, |c| is_space(*c)) {
+        anomalies.push((name_end, Anomaly::WhitespaceBeforeColon));
+    }
+
+    for (idx, chr) in line.iter().enumerate() {
+        if *chr == b'\n' && line.get(idx + 1).map_or(false, |c| is_space(*c)) {
+            anomalies.push((idx + 1, Anomaly::ObsFold));
+        }
+    }
+
+    bare_lfs(line, &mut anomalies);
+    anomalies.sort_by_key(|a| a.0);
+    anomalies
+}
+
+/// Any other line, i.e. an empty line or a line around a chunk.
+pub fn line(line: &[u8]) -> Vec<(usize, Anomaly)> {
+    let mut anomalies = Vec::new();
+    bare_lfs(line, &mut anomalies);
+    anomalies
+}
+
+
+#[test]
+fn test_request_line() {
+    let cases = vec![
+        (&b"GET / HTTP/1.1\r\n"[..], vec![]),
+        (&b"GET  / HTTP/1.1\r\n"[..], vec![(4, Anomaly::UnexpectedWhitespace)]),
+        (&b"GET /\tHTTP/1.1\r\n"[..], vec![(5, Anomaly::UnexpectedWhitespace)]),
+        (&b"GET / HTTP/1.1 \n"[..], vec![
+            (14, Anomaly::UnexpectedWhitespace), (15, Anomaly::BareLF)
+        ]),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(expected, request_line(input), "{:?}", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn test_response_line() {
+    let cases = vec![
+        (&b"HTTP/1.1 200 OK  then\r\n"[..], vec![]),
+        (&b"HTTP/1.1  200 OK\r\n"[..], vec![(9, Anomaly::UnexpectedWhitespace)]),
+        (&b"HTTP/1.1 200\r\n"[..], vec![(12, Anomaly::MissingReasonPhrase)]),
+        (&b"HTTP/1.1 200 \n"[..], vec![(13, Anomaly::MissingReasonPhrase), (13, Anomaly::BareLF)]),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(expected, response_line(input), "{:?}", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn test_header_line() {
+    let cases = vec![
+        (&b"Host: example.com\r\n"[..], vec![]),
+        (&b"Host : example.com\r\n"[..], vec![(4, Anomaly::WhitespaceBeforeColon)]),
+        (&b"X-Folded: a\r\n b\n"[..], vec![(13, Anomaly::ObsFold), (15, Anomaly::BareLF)]),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(expected, header_line(input), "{:?}", String::from_utf8_lossy(input));
+    }
+}
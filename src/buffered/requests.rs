@@ -68,35 +68,42 @@ impl BufferedRequest for BufferedRequestOwned {
 }
 
 impl <'r> HttpRequestCallbacks<'r> for BufferedRequestOwned {
-    fn on_request_line(&mut self, _: &mut HttpParser, request: RequestLine<'r>) {
-        self.method = str::from_utf8(request.method).unwrap().to_owned();
+    fn on_request_line(&mut self, _: &mut HttpParser, request: RequestLine<'r>)
+    -> CallbackResult<()> {
+        self.method = String::from_utf8_lossy(request.method.as_bytes()).into_owned();
         self.path = str::from_utf8(request.path).unwrap().to_owned();
         self.version = (
             request.version.0,
             request.version.1,
         );
+        Ok(())
     }
 
 }
 
 impl <'r> HttpMessageCallbacks<'r> for BufferedRequestOwned {
-    fn on_header(&mut self, _: &mut HttpParser, name: &'r [u8], value: &'r [u8]) {
+    fn on_header(&mut self, _: &mut HttpParser, name: &'r [u8], value: &'r [u8])
+    -> CallbackResult<()> {
         self.headers.insert(
             str::from_utf8(name).unwrap().to_owned(),
             value.to_owned()
         );
+        Ok(())
     }
-    fn on_headers_finished(&mut self, _: &mut HttpParser, _: BodyType) -> ExpectBody {
-        ExpectBody::Maybe
-    }
-    fn on_chunk(&mut self, _: &mut HttpParser, data: &[u8]) {
+    fn on_chunk(&mut self, _: &mut HttpParser, data: &[u8]) -> CallbackResult<()> {
         // TODO: push_all?
         for d in data {
             self.chunks.push(*d);
         }
+        Ok(())
+    }
+    fn on_trailer(&mut self, parser: &mut HttpParser, name: &'r [u8], value: &'r [u8])
+    -> CallbackResult<()> {
+        self.on_header(parser, name, value)
     }
-    fn on_end(&mut self, _: &mut HttpParser) {
+    fn on_end(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
         self.finished = true;
+        Ok(())
     }
 }
 
@@ -137,31 +144,39 @@ impl <'r> BufferedRequest for BufferedRequestCallback<'r> {
 
 
 impl <'r> HttpRequestCallbacks<'r> for BufferedRequestCallback<'r> {
-    fn on_request_line(&mut self, _: &mut HttpParser, request: RequestLine<'r>) {
-        self.method = str::from_utf8(request.method).unwrap();
+    fn on_request_line(&mut self, _: &mut HttpParser, request: RequestLine<'r>)
+    -> CallbackResult<()> {
+        // Methods are tokens, which are always ASCII.
+        self.method = str::from_utf8(request.method.as_bytes()).unwrap_or("");
         self.path = str::from_utf8(request.path).unwrap();
         self.version = (
             request.version.0,
             request.version.1,
         );
+        Ok(())
     }
 
 }
 
 impl <'r> HttpMessageCallbacks<'r> for BufferedRequestCallback<'r> {
-    fn on_header(&mut self, _: &mut HttpParser, name: &'r [u8], value: &'r [u8]) {
+    fn on_header(&mut self, _: &mut HttpParser, name: &'r [u8], value: &'r [u8])
+    -> CallbackResult<()> {
         self.headers.insert(str::from_utf8(name).unwrap(), value);
+        Ok(())
     }
-    fn on_headers_finished(&mut self, _: &mut HttpParser, _: BodyType) -> ExpectBody {
-        ExpectBody::Maybe
-    }
-    fn on_chunk(&mut self, _: &mut HttpParser, data: &[u8]) {
+    fn on_chunk(&mut self, _: &mut HttpParser, data: &[u8]) -> CallbackResult<()> {
         // TODO: push_all?
         for d in data {
             self.chunks.push(*d);
         }
+        Ok(())
+    }
+    fn on_trailer(&mut self, parser: &mut HttpParser, name: &'r [u8], value: &'r [u8])
+    -> CallbackResult<()> {
+        self.on_header(parser, name, value)
     }
-    fn on_end(&mut self, _: &mut HttpParser) {
+    fn on_end(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
         self.finished = true;
+        Ok(())
     }
 }
@@ -0,0 +1,123 @@
+/// How closely the parser sticks to the message syntax of RFC 9112.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// Rejects anything the RFC says recipients must reject, or that it only allows for the
+    /// sake of old implementations: more than a single space between the parts of a start line,
+    /// whitespace between a header name and its colon, line endings without a CR, and header
+    /// values continued onto the next line (obs-fold).
+    Strict,
+    /// Accepts all of the above, for talking to peers that don't follow the RFC.
+    Lenient,
+}
+
+impl Default for Strictness {
+    fn default() -> Strictness {
+        Strictness::Lenient
+    }
+}
+
+
+/// Configuration for an HttpParser.
+///
+/// By default nothing is limited. Parsers exposed to untrusted peers should set limits, as
+/// otherwise a peer can make the caller buffer an unbounded amount of data, e.g. by sending a
+/// header line that never ends.
+///
+/// Each limit that is exceeded is reported as its own `ErrorKind` variant, which
+/// includes both the limit and the size that was seen.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ParserConfig {
+    pub(crate) max_start_line_length: Option<usize>,
+    pub(crate) max_header_line_length: Option<usize>,
+    pub(crate) max_header_count: Option<usize>,
+    pub(crate) max_headers_size: Option<usize>,
+    pub(crate) max_chunk_extensions_length: Option<usize>,
+    pub(crate) max_trailer_count: Option<usize>,
+    pub(crate) max_body_size: Option<u64>,
+    pub(crate) allow_conflicting_framing: bool,
+    pub(crate) require_announced_trailers: bool,
+    pub(crate) strictness: Strictness,
+    pub(crate) recover_from_errors: bool,
+}
+
+impl ParserConfig {
+    pub fn new() -> ParserConfig {
+        ParserConfig::default()
+    }
+
+    /// The maximum length of the request or status line, including the line ending.
+    pub fn max_start_line_length(mut self, limit: usize) -> ParserConfig {
+        self.max_start_line_length = Some(limit);
+        self
+    }
+
+    /// The maximum length of a single header or trailer line, including any obs-fold
+    /// continuation lines and the line ending.
+    pub fn max_header_line_length(mut self, limit: usize) -> ParserConfig {
+        self.max_header_line_length = Some(limit);
+        self
+    }
+
+    /// The maximum number of headers in a message, not including trailers.
+    pub fn max_header_count(mut self, limit: usize) -> ParserConfig {
+        self.max_header_count = Some(limit);
+        self
+    }
+
+    /// The maximum total size of the header lines in a message, not including the start line
+    /// or trailers.
+    pub fn max_headers_size(mut self, limit: usize) -> ParserConfig {
+        self.max_headers_size = Some(limit);
+        self
+    }
+
+    /// The maximum length of the extensions following the size of a chunk, i.e. everything on
+    /// the chunk size line after the size itself, excluding the line ending.
+    pub fn max_chunk_extensions_length(mut self, limit: usize) -> ParserConfig {
+        self.max_chunk_extensions_length = Some(limit);
+        self
+    }
+
+    /// The maximum number of trailers after a chunked body.
+    pub fn max_trailer_count(mut self, limit: usize) -> ParserConfig {
+        self.max_trailer_count = Some(limit);
+        self
+    }
+
+    /// The maximum size of a message body. For chunked bodies this is the sum of the chunk
+    /// sizes.
+    pub fn max_body_size(mut self, limit: u64) -> ParserConfig {
+        self.max_body_size = Some(limit);
+        self
+    }
+
+    /// Whether to accept messages that have both a Transfer-Encoding and a Content-Length
+    /// header. The Transfer-Encoding always takes precedence, but as such messages are often an
+    /// attempt at request smuggling they are rejected by default.
+    pub fn allow_conflicting_framing(mut self, allow: bool) -> ParserConfig {
+        self.allow_conflicting_framing = allow;
+        self
+    }
+
+    /// Whether to reject trailers whose names weren't listed in a Trailer header of the message.
+    pub fn require_announced_trailers(mut self, require: bool) -> ParserConfig {
+        self.require_announced_trailers = require;
+        self
+    }
+
+    /// How strictly to parse message syntax. Lenient by default.
+    pub fn strictness(mut self, strictness: Strictness) -> ParserConfig {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Whether to carry on after an error, e.g. when analysing captured traffic. Instead of
+    /// being returned, each error is passed to `HttpMessageCallbacks::on_error` and the parser
+    /// skips ahead to the next line that looks like the start of a message, then calls
+    /// `on_resync` with the number of bytes it skipped. Errors from callbacks are still
+    /// returned.
+    pub fn recover_from_errors(mut self, recover: bool) -> ParserConfig {
+        self.recover_from_errors = recover;
+        self
+    }
+}
@@ -0,0 +1,272 @@
+//! Works out what is wrong with a line that the parsers in `nom_parsers` rejected.
+//!
+//! The nom parsers only say that a line is invalid, so when they fail we go back over the line
+//! to find the first byte that doesn't fit the grammar they accept. Each function returns the
+//! position of that byte in the input along with the reason.
+
+use errors::SyntaxError;
+use integer_decoder::hex_buf_to_int;
+use nom_parsers::{is_space, is_token_char, skip_space};
+
+
+/// Skips bytes that aren't whitespace or a line ending.
+fn skip_word(input: &[u8], pos: usize) -> usize {
+    input[pos..].iter()
+        .position(|c| is_space(*c) || *c == b'\r' || *c == b'\n')
+        .map_or(input.len(), |p| pos + p)
+}
+
+/// Checks for the line ending at `pos`. A bare LF only counts as one in lenient mode.
+fn line_ending(input: &[u8], pos: usize, strict: bool) -> (usize, SyntaxError) {
+    match input.get(pos) {
+        Some(&b'\r') if input.get(pos + 1) != Some(&b'\n') => (pos, SyntaxError::BareCR),
+        Some(&b'\n') if strict => (pos, SyntaxError::BareLF),
+        _ => (pos, SyntaxError::ExpectedLineEnding),
+    }
+}
+
+/// Checks for the end of a start line at `pos`, which lenient mode allows to be preceded by
+/// whitespace.
+fn end_of_line(input: &[u8], pos: usize, strict: bool) -> (usize, SyntaxError) {
+    if !strict {
+        return line_ending(input, skip_space(input, pos), strict);
+    }
+    match input.get(pos) {
+        Some(c) if is_space(*c) => (pos, SyntaxError::UnexpectedWhitespace),
+        _ => line_ending(input, pos, strict),
+    }
+}
+
+/// Skips the whitespace between two parts of a start line, which must be a single space in
+/// strict mode.
+fn separator(input: &[u8], pos: usize, strict: bool) -> Result<usize, (usize, SyntaxError)> {
+    if !strict {
+        return Ok(skip_space(input, pos));
+    }
+    match (input.get(pos), input.get(pos + 1)) {
+        (Some(&b' '), Some(c)) if is_space(*c) => Err((pos + 1, SyntaxError::UnexpectedWhitespace)),
+        (Some(&b' '), _) => Ok(pos + 1),
+        (Some(&b'\t'), _) => Err((pos, SyntaxError::UnexpectedWhitespace)),
+        _ => Ok(pos),
+    }
+}
+
+/// Checks for `HTTP/x.y` at `pos`, returning the position after it.
+fn version(input: &[u8], pos: usize) -> Result<usize, (usize, SyntaxError)> {
+    let rest = &input[pos..];
+    if rest.len() >= 8 && rest.starts_with(b"HTTP/") && rest[5].is_ascii_digit()
+        && rest[6] == b'.' && rest[7].is_ascii_digit() {
+        Ok(pos + 8)
+    } else {
+        Err((pos, SyntaxError::InvalidVersion))
+    }
+}
+
+pub fn request_line(input: &[u8], strict: bool) -> (usize, SyntaxError) {
+    let method_end = skip_word(input, 0);
+    if method_end == 0 || method_end == input.len() || !is_space(input[method_end]) {
+        return (method_end, SyntaxError::InvalidMethod);
+    }
+    if let Some(pos) = input[..method_end].iter().position(|c| !is_token_char(*c)) {
+        return (pos, SyntaxError::InvalidMethod);
+    }
+
+    let target_start = match separator(input, method_end, strict) {
+        Ok(target_start) => target_start,
+        Err(err) => return err,
+    };
+    let target_end = skip_word(input, target_start);
+    if target_end == target_start || target_end == input.len() || !is_space(input[target_end]) {
+        return (target_end, SyntaxError::InvalidRequestTarget);
+    }
+
+    let version_start = match separator(input, target_end, strict) {
+        Ok(version_start) => version_start,
+        Err(err) => return err,
+    };
+    match version(input, version_start) {
+        Ok(version_end) => end_of_line(input, version_end, strict),
+        Err(err) => err,
+    }
+}
+
+pub fn response_line(input: &[u8], strict: bool) -> (usize, SyntaxError) {
+    let version_end = match version(input, 0) {
+        Ok(version_end) => version_end,
+        Err(err) => return err,
+    };
+
+    let code_start = match separator(input, version_end, strict) {
+        Ok(code_start) => code_start,
+        Err(err) => return err,
+    };
+    let code = &input[code_start..];
+    if code.len() < 3 || !code[..3].iter().all(|c| c.is_ascii_digit()) || code[0] == b'0' {
+        return (code_start, SyntaxError::InvalidStatusCode);
+    }
+    if strict {
+        match input.get(code_start + 3) {
+            Some(&b' ') | Some(&b'\r') | Some(&b'\n') | None => {},
+            Some(_) => return (code_start + 3, SyntaxError::InvalidStatusCode),
+        }
+    }
+
+    let phrase_end = input[code_start..].iter()
+        .position(|c| *c == b'\r' || *c == b'\n')
+        .map_or(input.len(), |p| code_start + p);
+    line_ending(input, phrase_end, strict)
+}
+
+pub fn header_line(input: &[u8], strict: bool) -> (usize, SyntaxError) {
+    let name_end = input.iter().position(|c| !is_token_char(*c)).unwrap_or(input.len());
+    if name_end == 0 {
+        return match input.get(0) {
+            Some(&b'\r') => line_ending(input, 0, strict),
+            Some(&b'\n') if strict => (0, SyntaxError::BareLF),
+            Some(c) if strict && is_space(*c) => (0, SyntaxError::ObsFold),
+            _ => (0, SyntaxError::InvalidHeaderName),
+        };
+    }
+
+    let colon = skip_space(input, name_end);
+    match input.get(colon) {
+        Some(&b':') if strict && colon != name_end => {
+            return (name_end, SyntaxError::WhitespaceBeforeColon)
+        },
+        Some(&b':') => {},
+        Some(&b'\r') | Some(&b'\n') | None => return (colon, SyntaxError::MissingColon),
+        Some(_) if colon == name_end => return (colon, SyntaxError::InvalidHeaderName),
+        Some(_) => return (colon, SyntaxError::MissingColon),
+    }
+
+    // Both header parsers take any value, so it must be the line ending that's wrong.
+    let value_end = input[colon..].iter()
+        .position(|c| *c == b'\r' || *c == b'\n')
+        .map_or(input.len(), |p| colon + p);
+    line_ending(input, value_end, strict)
+}
+
+pub fn chunk_header(input: &[u8], strict: bool) -> (usize, SyntaxError) {
+    let size_end = input.iter().position(|c| !c.is_ascii_alphanumeric()).unwrap_or(input.len());
+    if size_end == 0 {
+        return (0, SyntaxError::InvalidChunkSize);
+    }
+    if let Some(pos) = input[..size_end].iter().position(|c| !c.is_ascii_hexdigit()) {
+        return (pos, SyntaxError::InvalidChunkSize);
+    }
+    if hex_buf_to_int(&input[..size_end]).is_err() {
+        return (0, SyntaxError::InvalidChunkSize);
+    }
+
+    let pos = skip_space(input, size_end);
+    match input.get(pos) {
+        Some(&b';') => (pos, SyntaxError::InvalidChunkExtension),
+        Some(&b'\r') | Some(&b'\n') | None => line_ending(input, pos, strict),
+        Some(_) => (pos, SyntaxError::InvalidChunkSize),
+    }
+}
+
+/// The line ending after the data of a chunk.
+pub fn chunk_data_end(input: &[u8], strict: bool) -> (usize, SyntaxError) {
+    line_ending(input, 0, strict)
+}
+
+
+#[test]
+fn test_request_line() {
+    let cases = vec![
+        (&b"GET\r\n"[..], (3, SyntaxError::InvalidMethod)),
+        (&b" / HTTP/1.1\r\n"[..], (0, SyntaxError::InvalidMethod)),
+        (&b"G(ET / HTTP/1.1\r\n"[..], (1, SyntaxError::InvalidMethod)),
+        (&b"GET /\r\n"[..], (5, SyntaxError::InvalidRequestTarget)),
+        (&b"GET / HTP/1.1\r\n"[..], (6, SyntaxError::InvalidVersion)),
+        (&b"GET / HTTP/1.x\r\n"[..], (6, SyntaxError::InvalidVersion)),
+        (&b"GET / HTTP/1.1\rX"[..], (14, SyntaxError::BareCR)),
+        (&b"GET / HTTP/1.1 x\r\n"[..], (15, SyntaxError::ExpectedLineEnding)),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(expected, request_line(input, false), "{:?}", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn test_response_line() {
+    let cases = vec![
+        (&b"HTTP/a.1 200 OK\r\n"[..], (0, SyntaxError::InvalidVersion)),
+        (&b"HTTP/1.1 2x0 OK\r\n"[..], (9, SyntaxError::InvalidStatusCode)),
+        (&b"HTTP/1.1 OK\r\n"[..], (9, SyntaxError::InvalidStatusCode)),
+        (&b"HTTP/1.1 000 OK\r\n"[..], (9, SyntaxError::InvalidStatusCode)),
+        (&b"HTTP/1.1 200 OK\rX"[..], (15, SyntaxError::BareCR)),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(expected, response_line(input, false), "{:?}", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn test_header_line() {
+    let cases = vec![
+        (&b"Host example.com\r\n"[..], (5, SyntaxError::MissingColon)),
+        (&b"Host\r\n"[..], (4, SyntaxError::MissingColon)),
+        (&b"Ho(st: example.com\r\n"[..], (2, SyntaxError::InvalidHeaderName)),
+        (&b": example.com\r\n"[..], (0, SyntaxError::InvalidHeaderName)),
+        (&b"\rX"[..], (0, SyntaxError::BareCR)),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(expected, header_line(input, false), "{:?}", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn test_chunk_header() {
+    let cases = vec![
+        (&b"\r\n"[..], (0, SyntaxError::InvalidChunkSize)),
+        (&b"5g\r\n"[..], (1, SyntaxError::InvalidChunkSize)),
+        (&b"10000000000000000\r\n"[..], (0, SyntaxError::InvalidChunkSize)),
+        (&b"5 x\r\n"[..], (2, SyntaxError::InvalidChunkSize)),
+        (&b"5;=\r\n"[..], (1, SyntaxError::InvalidChunkExtension)),
+        (&b"5\rX"[..], (1, SyntaxError::BareCR)),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(expected, chunk_header(input, false), "{:?}", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn test_strict() {
+    let request_lines = vec![
+        (&b"GET  / HTTP/1.1\r\n"[..], (4, SyntaxError::UnexpectedWhitespace)),
+        (&b"GET\t/ HTTP/1.1\r\n"[..], (3, SyntaxError::UnexpectedWhitespace)),
+        (&b"GET / HTTP/1.1 \r\n"[..], (14, SyntaxError::UnexpectedWhitespace)),
+        (&b"GET / HTTP/1.1\nHost"[..], (14, SyntaxError::BareLF)),
+    ];
+    for (input, expected) in request_lines {
+        assert_eq!(expected, request_line(input, true), "{:?}", String::from_utf8_lossy(input));
+    }
+
+    let response_lines = vec![
+        (&b"HTTP/1.1  200 OK\r\n"[..], (9, SyntaxError::UnexpectedWhitespace)),
+        (&b"HTTP/1.1 2000 OK\r\n"[..], (12, SyntaxError::InvalidStatusCode)),
+        (&b"HTTP/1.1 200 OK\nDate"[..], (15, SyntaxError::BareLF)),
+    ];
+    for (input, expected) in response_lines {
+        assert_eq!(expected, response_line(input, true), "{:?}", String::from_utf8_lossy(input));
+    }
+
+    let header_lines = vec![
+        (&b"Host : example.com\r\n"[..], (4, SyntaxError::WhitespaceBeforeColon)),
+        (&b"Host: example.com\nAccept"[..], (17, SyntaxError::BareLF)),
+        (&b" continued\r\n"[..], (0, SyntaxError::ObsFold)),
+        (&b"\nbody"[..], (0, SyntaxError::BareLF)),
+    ];
+    for (input, expected) in header_lines {
+        assert_eq!(expected, header_line(input, true), "{:?}", String::from_utf8_lossy(input));
+    }
+
+    assert_eq!((1, SyntaxError::BareLF), chunk_header(b"5\nHello", true));
+    assert_eq!((0, SyntaxError::BareLF), chunk_data_end(b"\n0", true));
+}
@@ -4,27 +4,173 @@ use std::error;
 use std::convert;
 
 #[derive(Debug, PartialEq, Eq)]
-pub enum IntegerDecodeError { TooLong(usize), InvalidChar(u8) }
+pub enum IntegerDecodeError { Empty, Overflow, InvalidChar(u8) }
+
+/// An error returned by a callback to stop the parser.
+pub type CallbackError = Box<dyn error::Error + Send + Sync>;
+
+/// An error from the parser, along with where in the stream it happened.
+#[derive(Debug, PartialEq)]
+pub struct HttpParserError {
+    pub kind: ErrorKind,
+    /// The offset from the start of the stream of the bytes that caused the error.
+    pub offset: u64,
+    /// The part of the message the parser was in.
+    pub phase: ParserPhase,
+    /// Up to `EXCERPT_LENGTH` bytes of the input, starting at `offset`. Empty if the error
+    /// wasn't caused by particular bytes, e.g. if the stream ended early.
+    pub excerpt: Vec<u8>,
+}
+
+pub const EXCERPT_LENGTH: usize = 32;
+
+/// The part of a message the parser was in when an error happened.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParserPhase { StartLine, Headers, Body, ChunkHeader, Trailers, Finished }
+
+/// What was wrong with a line that couldn't be parsed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SyntaxError {
+    InvalidMethod,
+    InvalidRequestTarget,
+    InvalidVersion,
+    InvalidStatusCode,
+    InvalidHeaderName,
+    MissingColon,
+    InvalidHeaderValue,
+    InvalidChunkSize,
+    InvalidChunkExtension,
+    /// A CR that isn't followed by a LF.
+    BareCR,
+    /// Something other than the end of the line where the line should have ended.
+    ExpectedLineEnding,
+    /// A LF that isn't preceded by a CR. Only rejected in strict mode.
+    BareLF,
+    /// Whitespace other than a single space between the parts of a start line, or after the
+    /// version of a request line. Only rejected in strict mode.
+    UnexpectedWhitespace,
+    /// Whitespace between a header name and the colon. Only rejected in strict mode.
+    WhitespaceBeforeColon,
+    /// A header value continued onto the next line. Only rejected in strict mode.
+    ObsFold,
+}
 
 #[derive(Debug)]
-pub enum HttpParserError {
-    BadFirstLine,
-    BadHeader,
+pub enum ErrorKind {
+    BadFirstLine(SyntaxError),
+    BadHeader(SyntaxError),
     BadHeaderValue(HttpHeaderParseError),
-    BadBodyChunkHeader,
+    BadBodyChunkHeader(SyntaxError),
+    UnexpectedEof,
+    StartLineTooLong { limit: usize, size: usize },
+    HeaderLineTooLong { limit: usize, size: usize },
+    TooManyHeaders { limit: usize, count: usize },
+    HeadersTooLarge { limit: usize, size: usize },
+    ChunkExtensionsTooLong { limit: usize, size: usize },
+    /// The hex digits of a chunk size run on for longer than any real chunk size could. The
+    /// limit is fixed, as leading zeros are the only reason for more than 16 digits.
+    ChunkSizeTooLong { limit: usize, size: usize },
+    TooManyTrailers { limit: usize, count: usize },
+    BodyTooLarge { limit: u64, size: u64 },
+    UnannouncedTrailer,
+    Callback(CallbackError),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum HttpHeaderParseError {
     ContentLength(IntegerDecodeError),
     UnrecognizedTransferEncoding,
+    /// Content-Length was given multiple times with different values.
+    ConflictingContentLength,
+    /// Both Transfer-Encoding and Content-Length were given.
+    TransferEncodingWithContentLength,
+    /// A request's Transfer-Encoding doesn't end with chunked, so its length can't be determined.
+    ChunkedNotFinal,
+}
+
+
+/// Callback errors can't be compared themselves, so they're equal if their messages are.
+impl PartialEq for ErrorKind {
+    fn eq(&self, other: &ErrorKind) -> bool {
+        match (self, other) {
+            (ErrorKind::BadFirstLine(a), ErrorKind::BadFirstLine(b)) => a == b,
+            (ErrorKind::BadHeader(a), ErrorKind::BadHeader(b)) => a == b,
+            (ErrorKind::BadHeaderValue(a), ErrorKind::BadHeaderValue(b)) => a == b,
+            (ErrorKind::BadBodyChunkHeader(a), ErrorKind::BadBodyChunkHeader(b)) => a == b,
+            (ErrorKind::UnexpectedEof, ErrorKind::UnexpectedEof) => true,
+            (ErrorKind::StartLineTooLong { limit: l1, size: s1 },
+             ErrorKind::StartLineTooLong { limit: l2, size: s2 }) => (l1, s1) == (l2, s2),
+            (ErrorKind::HeaderLineTooLong { limit: l1, size: s1 },
+             ErrorKind::HeaderLineTooLong { limit: l2, size: s2 }) => (l1, s1) == (l2, s2),
+            (ErrorKind::TooManyHeaders { limit: l1, count: c1 },
+             ErrorKind::TooManyHeaders { limit: l2, count: c2 }) => (l1, c1) == (l2, c2),
+            (ErrorKind::HeadersTooLarge { limit: l1, size: s1 },
+             ErrorKind::HeadersTooLarge { limit: l2, size: s2 }) => (l1, s1) == (l2, s2),
+            (ErrorKind::ChunkExtensionsTooLong { limit: l1, size: s1 },
+             ErrorKind::ChunkExtensionsTooLong { limit: l2, size: s2 }) => (l1, s1) == (l2, s2),
+            (ErrorKind::ChunkSizeTooLong { limit: l1, size: s1 },
+             ErrorKind::ChunkSizeTooLong { limit: l2, size: s2 }) => (l1, s1) == (l2, s2),
+            (ErrorKind::TooManyTrailers { limit: l1, count: c1 },
+             ErrorKind::TooManyTrailers { limit: l2, count: c2 }) => (l1, c1) == (l2, c2),
+            (ErrorKind::BodyTooLarge { limit: l1, size: s1 },
+             ErrorKind::BodyTooLarge { limit: l2, size: s2 }) => (l1, s1) == (l2, s2),
+            (ErrorKind::UnannouncedTrailer, ErrorKind::UnannouncedTrailer) => true,
+            (ErrorKind::Callback(a), ErrorKind::Callback(b)) => a.to_string() == b.to_string(),
+            _ => false,
+        }
+    }
 }
 
 
+impl HttpParserError {
+    /// An error caused by the bytes at `pos` in `input`. The offset is relative to `input` until
+    /// the parser moves it along with `shift`.
+    pub(crate) fn at(input: &[u8], pos: usize, kind: ErrorKind) -> HttpParserError {
+        HttpParserError::from(kind).near(input, pos)
+    }
+
+    /// Points an error that doesn't have an excerpt yet at the bytes at `pos` in `input`.
+    pub(crate) fn near(mut self, input: &[u8], pos: usize) -> HttpParserError {
+        if self.excerpt.is_empty() {
+            let end = ::std::cmp::min(input.len(), pos + EXCERPT_LENGTH);
+            self.offset += pos as u64;
+            self.excerpt = input[pos..end].to_owned();
+        }
+        self
+    }
+
+    pub(crate) fn shift(mut self, offset: u64) -> HttpParserError {
+        self.offset += offset;
+        self
+    }
+
+    pub(crate) fn in_phase(mut self, phase: ParserPhase) -> HttpParserError {
+        self.phase = phase;
+        self
+    }
+}
+
+impl convert::From<ErrorKind> for HttpParserError {
+    fn from(kind: ErrorKind) -> Self {
+        HttpParserError {
+            kind: kind,
+            offset: 0,
+            phase: ParserPhase::StartLine,
+            excerpt: Vec::new(),
+        }
+    }
+}
 
 impl convert::From<HttpHeaderParseError> for HttpParserError {
     fn from(e: HttpHeaderParseError) -> Self {
-        HttpParserError::BadHeaderValue(e)
+        HttpParserError::from(ErrorKind::BadHeaderValue(e))
+    }
+}
+
+
+impl convert::From<CallbackError> for HttpParserError {
+    fn from(e: CallbackError) -> Self {
+        HttpParserError::from(ErrorKind::Callback(e))
     }
 }
 
@@ -33,19 +179,18 @@ impl error::Error for IntegerDecodeError {
     fn description(&self) -> &str {
         "failed to parse integer"
     }
-
-    fn cause(&self) -> Option<&error::Error> {
-        None
-    }
 }
 
 impl fmt::Display for IntegerDecodeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            IntegerDecodeError::TooLong(len) => write!(
+            IntegerDecodeError::Empty => write!(
+                f,
+                "Could not parse int: The supplied buffer was empty.",
+            ),
+            IntegerDecodeError::Overflow => write!(
                 f,
-                "Could not parse int: The supplied buffer was too long. Input size {} bytes",
-                len,
+                "Could not parse int: The value does not fit in 64 bits.",
             ),
             IntegerDecodeError::InvalidChar(chr) => write!(
                 f,
@@ -62,10 +207,13 @@ impl error::Error for HttpHeaderParseError {
         "failed to parse header value"
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             &HttpHeaderParseError::ContentLength(ref e) => Some(e),
             &HttpHeaderParseError::UnrecognizedTransferEncoding => None,
+            &HttpHeaderParseError::ConflictingContentLength => None,
+            &HttpHeaderParseError::TransferEncodingWithContentLength => None,
+            &HttpHeaderParseError::ChunkedNotFinal => None,
         }
     }
 }
@@ -79,42 +227,142 @@ impl fmt::Display for HttpHeaderParseError {
             &HttpHeaderParseError::UnrecognizedTransferEncoding => write!(
                 f, "HttpHeaderParseError: Unrecognized Transfer-Encoding.",
             ),
+            &HttpHeaderParseError::ConflictingContentLength => write!(
+                f, "HttpHeaderParseError: Conflicting Content-Length values.",
+            ),
+            &HttpHeaderParseError::TransferEncodingWithContentLength => write!(
+                f, "HttpHeaderParseError: Both Transfer-Encoding and Content-Length given.",
+            ),
+            &HttpHeaderParseError::ChunkedNotFinal => write!(
+                f, "HttpHeaderParseError: Transfer-Encoding does not end with chunked.",
+            ),
         }
     }
 }
 
 
 
-impl error::Error for HttpParserError {
+impl error::Error for ErrorKind {
     fn description(&self) -> &str {
         "failed to parse HTTP message"
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
-            &HttpParserError::BadFirstLine => None,
-            &HttpParserError::BadHeader => None,
-            &HttpParserError::BadHeaderValue(ref err) => Some(err),
-            &HttpParserError::BadBodyChunkHeader => None,
+            &ErrorKind::BadFirstLine(_) => None,
+            &ErrorKind::BadHeader(_) => None,
+            &ErrorKind::BadHeaderValue(ref err) => Some(err),
+            &ErrorKind::BadBodyChunkHeader(_) => None,
+            &ErrorKind::UnexpectedEof => None,
+            &ErrorKind::StartLineTooLong{..} => None,
+            &ErrorKind::HeaderLineTooLong{..} => None,
+            &ErrorKind::TooManyHeaders{..} => None,
+            &ErrorKind::HeadersTooLarge{..} => None,
+            &ErrorKind::ChunkExtensionsTooLong{..} => None,
+            &ErrorKind::ChunkSizeTooLong{..} => None,
+            &ErrorKind::TooManyTrailers{..} => None,
+            &ErrorKind::BodyTooLarge{..} => None,
+            &ErrorKind::UnannouncedTrailer => None,
+            &ErrorKind::Callback(ref err) => Some(&**err),
         }
     }
 }
 
-impl fmt::Display for HttpParserError {
+impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &HttpParserError::BadFirstLine => write!(
-                f, "HttpParserError: Invalid first line."
+            &ErrorKind::BadFirstLine(reason) => write!(
+                f, "Invalid first line: {}.", reason
+            ),
+            &ErrorKind::BadHeader(reason) => write!(
+                f, "Invalid header line: {}.", reason
+            ),
+            &ErrorKind::BadHeaderValue(ref err) => write!(
+                f, "{}", err
+            ),
+            &ErrorKind::BadBodyChunkHeader(reason) => write!(
+                f, "Invalid chunked header: {}.", reason
+            ),
+            &ErrorKind::UnexpectedEof => write!(
+                f, "Stream ended part way through a message."
+            ),
+            &ErrorKind::StartLineTooLong{limit, size} => write!(
+                f, "First line is {} bytes, limit is {}.", size, limit
             ),
-            &HttpParserError::BadHeader => write!(
-                f, "HttpParserError: Invalid header line."
+            &ErrorKind::HeaderLineTooLong{limit, size} => write!(
+                f, "Header line is {} bytes, limit is {}.", size, limit
             ),
-            &HttpParserError::BadHeaderValue(ref err) => write!(
-                f, "HttpParserError: {}", err
+            &ErrorKind::TooManyHeaders{limit, count} => write!(
+                f, "Received {} headers, limit is {}.", count, limit
             ),
-            &HttpParserError::BadBodyChunkHeader => write!(
-                f, "HttpParserError: Invalid chunked header."
+            &ErrorKind::HeadersTooLarge{limit, size} => write!(
+                f, "Headers are {} bytes, limit is {}.", size, limit
+            ),
+            &ErrorKind::ChunkExtensionsTooLong{limit, size} => write!(
+                f, "Chunk extensions are {} bytes, limit is {}.", size, limit
+            ),
+            &ErrorKind::ChunkSizeTooLong{limit, size} => write!(
+                f, "Chunk size is at least {} digits, limit is {}.", size, limit
+            ),
+            &ErrorKind::TooManyTrailers{limit, count} => write!(
+                f, "Received {} trailers, limit is {}.", count, limit
+            ),
+            &ErrorKind::BodyTooLarge{limit, size} => write!(
+                f, "Body is at least {} bytes, limit is {}.", size, limit
+            ),
+            &ErrorKind::UnannouncedTrailer => write!(
+                f, "Received a trailer that wasn't announced in a Trailer header."
+            ),
+            &ErrorKind::Callback(ref err) => write!(
+                f, "Callback failed: {}", err
             ),
         }
     }
 }
+
+
+impl error::Error for HttpParserError {
+    fn description(&self) -> &str {
+        "failed to parse HTTP message"
+    }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.kind.source()
+    }
+}
+
+impl fmt::Display for HttpParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(
+            f, "HttpParserError: {} (at offset {} in {:?}", self.kind, self.offset, self.phase
+        ));
+        if self.excerpt.len() > 0 {
+            try!(write!(f, ", near {:?}", String::from_utf8_lossy(&self.excerpt)));
+        }
+        write!(f, ")")
+    }
+}
+
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match *self {
+            SyntaxError::InvalidMethod => "invalid method",
+            SyntaxError::InvalidRequestTarget => "invalid request target",
+            SyntaxError::InvalidVersion => "invalid HTTP version",
+            SyntaxError::InvalidStatusCode => "invalid status code",
+            SyntaxError::InvalidHeaderName => "invalid header name",
+            SyntaxError::MissingColon => "missing colon after header name",
+            SyntaxError::InvalidHeaderValue => "invalid header value",
+            SyntaxError::InvalidChunkSize => "invalid chunk size",
+            SyntaxError::InvalidChunkExtension => "invalid chunk extension",
+            SyntaxError::BareCR => "CR not followed by LF",
+            SyntaxError::ExpectedLineEnding => "expected end of line",
+            SyntaxError::BareLF => "LF not preceded by CR",
+            SyntaxError::UnexpectedWhitespace => "unexpected whitespace",
+            SyntaxError::WhitespaceBeforeColon => "whitespace before colon",
+            SyntaxError::ObsFold => "header value continued onto the next line",
+        };
+        write!(f, "{}", reason)
+    }
+}
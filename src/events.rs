@@ -0,0 +1,171 @@
+use anomalies::Anomaly;
+use errors::HttpParserError;
+use nom_parsers::{ChunkHeader, RequestLine, ResponseLine};
+use parser::*;
+
+
+/// An event returned by `HttpParser::next_event`.
+#[derive(Debug, PartialEq)]
+pub enum HttpEvent<'r> {
+    RequestLine(RequestLine<'r>),
+    ResponseLine(ResponseLine<'r>),
+    /// A header, as a name and a value.
+    Header(&'r [u8], &'r [u8]),
+    HeadersComplete(BodyType),
+    /// The size and extensions of a chunk of a chunked body, including the final zero sized chunk.
+    ChunkHeader(ChunkHeader<'r>),
+    /// Part of the body, after any chunked encoding has been removed.
+    Body(&'r [u8]),
+    /// A trailer after a chunked body, as a name and a value.
+    Trailer(&'r [u8], &'r [u8]),
+    MessageComplete,
+    /// Something questionable that the parser accepted, with its offset in the stream. Comes
+    /// after the event for the line it's on, if there is one.
+    Anomaly(Anomaly, u64),
+    /// An error the parser recovered from, see `ParserConfig::recover_from_errors`. The current
+    /// message is abandoned.
+    Error(HttpParserError),
+    /// The parser found the start of a message after an error, having skipped this many bytes.
+    Resync(u64),
+    /// The input ran out before the next event.
+    NeedMore,
+}
+
+/// Callbacks that pause the parser after each callback that produces an event, so that a call
+/// to `parse_request`/`parse_response` produces at most one event.
+///
+/// Bodies are always expected, as there's no way to return `ExpectBody::No` through the pull
+/// API. A response parser still knows when a response has no body if it's told the request
+/// methods with `push_request_method`, and `skip_body` discards a body that isn't wanted.
+///
+/// Anomalies, errors and resyncs can come after another event in the same step, so they're
+/// queued in the parser, which can hold them as they don't borrow the input.
+struct EventCollector<'r> {
+    event: Option<HttpEvent<'r>>,
+}
+
+impl <'r> EventCollector<'r> {
+    fn collect(&mut self, parser: &mut HttpParser, event: HttpEvent<'r>) -> CallbackResult<()> {
+        self.event = Some(event);
+        parser.pause();
+        Ok(())
+    }
+
+    fn queue(&mut self, parser: &mut HttpParser, event: HttpEvent<'static>) -> CallbackResult<()> {
+        parser.pending_events.push_back(event);
+        parser.pause();
+        Ok(())
+    }
+
+    /// The event to return, ahead of any that were queued.
+    fn take(self, parser: &mut HttpParser) -> Option<HttpEvent<'r>> {
+        self.event.or_else(|| parser.pending_events.pop_front())
+    }
+}
+
+impl <'r> HttpMessageCallbacks<'r> for EventCollector<'r> {
+    fn on_header(&mut self, parser: &mut HttpParser, name: &'r [u8], value: &'r [u8])
+    -> CallbackResult<()> {
+        self.collect(parser, HttpEvent::Header(name, value))
+    }
+    fn on_headers_finished(&mut self, parser: &mut HttpParser, body_type: BodyType)
+    -> CallbackResult<ExpectBody> {
+        try!(self.collect(parser, HttpEvent::HeadersComplete(body_type)));
+        Ok(ExpectBody::Maybe)
+    }
+    fn on_chunk_header(&mut self, parser: &mut HttpParser, header: ChunkHeader<'r>)
+    -> CallbackResult<()> {
+        self.collect(parser, HttpEvent::ChunkHeader(header))
+    }
+    fn on_chunk(&mut self, parser: &mut HttpParser, data: &'r [u8]) -> CallbackResult<()> {
+        self.collect(parser, HttpEvent::Body(data))
+    }
+    fn on_trailer(&mut self, parser: &mut HttpParser, name: &'r [u8], value: &'r [u8])
+    -> CallbackResult<()> {
+        self.collect(parser, HttpEvent::Trailer(name, value))
+    }
+    fn on_end(&mut self, parser: &mut HttpParser) -> CallbackResult<()> {
+        self.collect(parser, HttpEvent::MessageComplete)
+    }
+    fn on_anomaly(&mut self, parser: &mut HttpParser, kind: Anomaly, offset: u64)
+    -> CallbackResult<()> {
+        self.queue(parser, HttpEvent::Anomaly(kind, offset))
+    }
+    fn on_error(&mut self, parser: &mut HttpParser, error: HttpParserError) -> CallbackResult<()> {
+        self.queue(parser, HttpEvent::Error(error))
+    }
+    fn on_resync(&mut self, parser: &mut HttpParser, skipped: u64) -> CallbackResult<()> {
+        self.queue(parser, HttpEvent::Resync(skipped))
+    }
+}
+
+impl <'r> HttpRequestCallbacks<'r> for EventCollector<'r> {
+    fn on_request_line(&mut self, parser: &mut HttpParser, request: RequestLine<'r>)
+    -> CallbackResult<()> {
+        self.collect(parser, HttpEvent::RequestLine(request))
+    }
+}
+
+impl <'r> HttpResponseCallbacks<'r> for EventCollector<'r> {
+    fn on_response_line(&mut self, parser: &mut HttpParser, response: ResponseLine<'r>)
+    -> CallbackResult<()> {
+        self.collect(parser, HttpEvent::ResponseLine(response))
+    }
+}
+
+
+impl HttpParser {
+    /// Parses the input up to the next event, as an alternative to the callback API.
+    ///
+    /// Returns the event along with the number of bytes of the input that were consumed. The
+    /// rest of the input, plus any more that has been received, should be passed to the next
+    /// call. `HttpEvent::NeedMore` means more input is needed before the next event, but some
+    /// input may still have been consumed, e.g. a chunk size line.
+    ///
+    /// Once the parser is upgraded (see `is_upgraded`) no more events are produced, and the
+    /// input after the `MessageComplete` event belongs to the new protocol.
+    ///
+    /// When the stream ends `finish_event` should be called, as a body delimited by the end of
+    /// the stream is never complete until then.
+    pub fn next_event<'r>(&mut self, input: &'r [u8])
+    -> HttpParserResult<(HttpEvent<'r>, usize)> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok((event, 0));
+        }
+
+        let mut collector = EventCollector { event: None };
+        let (remaining, _) = try!(self.parse(&mut collector, input));
+        let consumed = input.len() - remaining.len();
+
+        match collector.take(self) {
+            Some(event) => {
+                self.resume();
+                Ok((event, consumed))
+            },
+            None => Ok((HttpEvent::NeedMore, consumed)),
+        }
+    }
+
+    /// Tells the parser that the stream has ended, as the pull API's equivalent of `finish`.
+    ///
+    /// Returns `HttpEvent::MessageComplete` if this completes the current message, e.g. one
+    /// whose body is delimited by the end of the stream, or None if there was no message in
+    /// progress. Returns `ErrorKind::UnexpectedEof` if the stream ended part way through a
+    /// message.
+    ///
+    /// Any events still waiting to be returned by `next_event` are returned first, so this
+    /// should be called until it returns None.
+    pub fn finish_event(&mut self) -> HttpParserResult<Option<HttpEvent<'static>>> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok(Some(event));
+        }
+
+        let mut collector = EventCollector { event: None };
+        try!(self.finish(&mut collector));
+        let event = collector.take(self);
+        if event.is_some() {
+            self.resume();
+        }
+        Ok(event)
+    }
+}
@@ -0,0 +1,364 @@
+use errors::*;
+use integer_decoder;
+use nom_parsers::{self, is_space, is_token_char};
+use parser::BodyType;
+
+
+/// Splits a header value into the elements of a comma separated list, trimming the whitespace
+/// around each element and skipping empty elements.
+pub fn comma_separated<'a>(value: &'a [u8]) -> impl Iterator<Item=&'a [u8]> + 'a {
+    value.split(|c| *c == b',').map(trim).filter(|v| v.len() > 0)
+}
+
+fn trim(value: &[u8]) -> &[u8] {
+    let start = nom_parsers::skip_space(value, 0);
+    let end = value.iter().rposition(|c| !is_space(*c)).map_or(start, |e| e + 1);
+    &value[start..end]
+}
+
+fn trim_start(value: &[u8]) -> &[u8] {
+    &value[nom_parsers::skip_space(value, 0)..]
+}
+
+/// Splits a token off the front of the value. The token is empty if the value doesn't start
+/// with one.
+fn split_token(value: &[u8]) -> (&[u8], &[u8]) {
+    let end = value.iter().position(|c| !is_token_char(*c)).unwrap_or(value.len());
+    (&value[..end], &value[end..])
+}
+
+/// Splits a quoted-string off the front of the value, which must start with a DQUOTE.
+fn split_quoted_string(value: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut idx = 1;
+    while idx < value.len() {
+        match value[idx] {
+            b'\\' => idx += 2,
+            b'"' => return Some((&value[..idx + 1], &value[idx + 1..])),
+            _ => idx += 1,
+        }
+    }
+    None
+}
+
+/// Parses a Content-Length value.
+///
+/// Some implementations send the header as a list of identical values, e.g. `5, 5`, which is
+/// accepted. A list of differing values is an error.
+pub fn content_length(value: &[u8]) -> Result<u64, HttpHeaderParseError> {
+    let mut length = None;
+    for element in comma_separated(value) {
+        let len = try!(
+            integer_decoder::dec_buf_to_int(element).map_err(HttpHeaderParseError::ContentLength)
+        );
+        match length {
+            Some(prev) if prev != len => return Err(HttpHeaderParseError::ConflictingContentLength),
+            _ => length = Some(len),
+        }
+    }
+    length.ok_or(HttpHeaderParseError::ContentLength(IntegerDecodeError::Empty))
+}
+
+
+/// A transfer coding from a Transfer-Encoding header.
+#[derive(PartialEq,Eq,Debug,Clone,Copy)]
+pub enum TransferCoding { Chunked, Compress, Deflate, Gzip }
+
+impl TransferCoding {
+    fn from_name(name: &[u8]) -> Option<TransferCoding> {
+        if b"chunked".eq_ignore_ascii_case(name) {
+            Some(TransferCoding::Chunked)
+        } else if b"gzip".eq_ignore_ascii_case(name) || b"x-gzip".eq_ignore_ascii_case(name) {
+            Some(TransferCoding::Gzip)
+        } else if b"deflate".eq_ignore_ascii_case(name) {
+            Some(TransferCoding::Deflate)
+        } else if b"compress".eq_ignore_ascii_case(name) || b"x-compress".eq_ignore_ascii_case(name) {
+            Some(TransferCoding::Compress)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses a Transfer-Encoding value, appending the codings to `codings`.
+///
+/// Any parameters on the codings are validated but otherwise ignored, as none of the codings
+/// we understand define any.
+pub fn transfer_codings(value: &[u8], codings: &mut Vec<TransferCoding>)
+-> Result<(), HttpHeaderParseError> {
+    let mut rest = value;
+    loop {
+        // Skip any empty list elements.
+        rest = trim_start(rest);
+        while rest.len() > 0 && rest[0] == b',' {
+            rest = trim_start(&rest[1..]);
+        }
+        if rest.len() == 0 {
+            return Ok(());
+        }
+
+        let (name, after_name) = split_token(rest);
+        match TransferCoding::from_name(name) {
+            Some(coding) => codings.push(coding),
+            None => return Err(HttpHeaderParseError::UnrecognizedTransferEncoding),
+        }
+
+        rest = trim_start(after_name);
+        while rest.len() > 0 && rest[0] == b';' {
+            let (param_name, after_param_name) = split_token(trim_start(&rest[1..]));
+            let after_param_name = trim_start(after_param_name);
+            if param_name.len() == 0 || after_param_name.len() == 0 || after_param_name[0] != b'=' {
+                return Err(HttpHeaderParseError::UnrecognizedTransferEncoding);
+            }
+
+            let param_value = trim_start(&after_param_name[1..]);
+            let (param_value, after_param_value) = if param_value.len() > 0 && param_value[0] == b'"' {
+                match split_quoted_string(param_value) {
+                    Some(split) => split,
+                    None => return Err(HttpHeaderParseError::UnrecognizedTransferEncoding),
+                }
+            } else {
+                split_token(param_value)
+            };
+            if param_value.len() == 0 {
+                return Err(HttpHeaderParseError::UnrecognizedTransferEncoding);
+            }
+
+            rest = trim_start(after_param_value);
+        }
+
+        if rest.len() > 0 && rest[0] != b',' {
+            return Err(HttpHeaderParseError::UnrecognizedTransferEncoding);
+        }
+    }
+}
+
+
+/// Whether an Expect header value includes the 100-continue expectation.
+pub fn expects_continue(value: &[u8]) -> bool {
+    comma_separated(value).any(|e| b"100-continue".eq_ignore_ascii_case(e))
+}
+
+
+/// The connection options from a message's Connection headers that the parser cares about,
+/// along with whether it has an Upgrade header.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionOptions {
+    pub close: bool,
+    pub keep_alive: bool,
+    pub upgrade: bool,
+    /// Whether an Upgrade header lists any protocols.
+    pub upgrade_protocols: bool,
+}
+
+impl ConnectionOptions {
+    pub fn on_header(&mut self, name: &[u8], value: &[u8]) {
+        if b"upgrade".eq_ignore_ascii_case(name) {
+            self.upgrade_protocols |= comma_separated(value).next().is_some();
+            return;
+        }
+        if !b"connection".eq_ignore_ascii_case(name) {
+            return;
+        }
+
+        for option in comma_separated(value) {
+            if b"close".eq_ignore_ascii_case(option) {
+                self.close = true;
+            } else if b"keep-alive".eq_ignore_ascii_case(option) {
+                self.keep_alive = true;
+            } else if b"upgrade".eq_ignore_ascii_case(option) {
+                self.upgrade = true;
+            }
+        }
+    }
+
+    /// Whether a request with these options asks to switch to another protocol. Upgrade is
+    /// ignored in HTTP/1.0 requests, as per RFC 9110 section 7.8.
+    pub fn upgrade_requested(&self, version: (u8, u8)) -> bool {
+        self.upgrade && self.upgrade_protocols && version >= (1, 1)
+    }
+
+    /// Whether the connection may be reused after a message with these options, given the
+    /// version of HTTP used for the message.
+    pub fn keep_alive(&self, version: (u8, u8)) -> bool {
+        if self.close {
+            false
+        } else if version >= (1, 1) {
+            true
+        } else {
+            self.keep_alive
+        }
+    }
+}
+
+
+/// The headers that determine how the body of a message is framed, as seen so far.
+#[derive(Debug, Clone, Default)]
+pub struct Framing {
+    content_length: Option<u64>,
+    transfer_encoding: bool,
+    transfer_codings: Vec<TransferCoding>,
+}
+
+impl Framing {
+    pub fn transfer_codings(&self) -> &[TransferCoding] {
+        &self.transfer_codings
+    }
+
+    pub fn has_content_length(&self) -> bool {
+        self.content_length.is_some()
+    }
+
+    pub fn has_transfer_encoding(&self) -> bool {
+        self.transfer_encoding
+    }
+
+    /// Whether there's both a Transfer-Encoding and a Content-Length.
+    pub fn is_conflicting(&self) -> bool {
+        self.transfer_encoding && self.content_length.is_some()
+    }
+
+    pub fn on_header(&mut self, name: &[u8], value: &[u8]) -> Result<(), HttpHeaderParseError> {
+        if b"transfer-encoding".eq_ignore_ascii_case(name) {
+            self.transfer_encoding = true;
+            try!(transfer_codings(value, &mut self.transfer_codings));
+        } else if b"content-length".eq_ignore_ascii_case(name) {
+            let len = try!(content_length(value));
+            match self.content_length {
+                Some(prev) if prev != len => {
+                    return Err(HttpHeaderParseError::ConflictingContentLength)
+                },
+                _ => self.content_length = Some(len),
+            }
+        }
+        Ok(())
+    }
+
+    /// Works out the body type from the headers, as per RFC 9112 section 6.3.
+    ///
+    /// Returns None if the headers don't say, in which case the default for the type of message
+    /// applies.
+    pub fn body_type(&self, is_request: bool, allow_conflicting: bool)
+    -> Result<Option<BodyType>, HttpHeaderParseError> {
+        if self.transfer_encoding {
+            if self.content_length.is_some() && !allow_conflicting {
+                return Err(HttpHeaderParseError::TransferEncodingWithContentLength);
+            }
+
+            // Chunked must be the final coding, and can only be applied once.
+            let chunked_final = self.transfer_codings.last() == Some(&TransferCoding::Chunked);
+            let chunked_count = self.transfer_codings.iter()
+                .filter(|c| **c == TransferCoding::Chunked)
+                .count();
+
+            if chunked_final && chunked_count == 1 {
+                Ok(Some(BodyType::Chunked))
+            } else if is_request {
+                Err(HttpHeaderParseError::ChunkedNotFinal)
+            } else {
+                Ok(Some(BodyType::EOF))
+            }
+        } else {
+            Ok(self.content_length.map(BodyType::Length))
+        }
+    }
+}
+
+
+#[test]
+fn test_content_length() {
+    assert_eq!(5, content_length(b"5").unwrap());
+    assert_eq!(5, content_length(b"5, 5").unwrap());
+    assert_eq!(5, content_length(b"5,5,").unwrap());
+
+    match content_length(b"5, 6") {
+        Err(HttpHeaderParseError::ConflictingContentLength) => {},
+        r => panic!("Expected ConflictingContentLength: {:?}", r),
+    }
+    match content_length(b"") {
+        Err(HttpHeaderParseError::ContentLength(IntegerDecodeError::Empty)) => {},
+        r => panic!("Expected empty Content-Length error: {:?}", r),
+    }
+}
+
+#[test]
+fn test_transfer_codings() {
+    let cases = vec![
+        (&b"chunked"[..], vec![TransferCoding::Chunked]),
+        (&b"CHUNKED "[..], vec![TransferCoding::Chunked]),
+        (&b"gzip, chunked"[..], vec![TransferCoding::Gzip, TransferCoding::Chunked]),
+        (&b"x-gzip,,deflate ,chunked"[..], vec![
+            TransferCoding::Gzip, TransferCoding::Deflate, TransferCoding::Chunked
+        ]),
+        (&b"compress;level=1, chunked ; foo = \"a, \\\" b\""[..], vec![
+            TransferCoding::Compress, TransferCoding::Chunked
+        ]),
+        (&b""[..], vec![]),
+    ];
+
+    for (value, expected) in cases {
+        let mut codings = Vec::new();
+        transfer_codings(value, &mut codings).unwrap();
+        assert_eq!(expected, codings);
+    }
+
+    let invalid = [
+        &b"br"[..],
+        &b"chunked;"[..],
+        &b"chunked;foo"[..],
+        &b"chunked;foo="[..],
+        &b"chunked;foo=\"bar"[..],
+        &b"chunked gzip"[..],
+    ];
+
+    for value in invalid.iter() {
+        match transfer_codings(value, &mut Vec::new()) {
+            Err(HttpHeaderParseError::UnrecognizedTransferEncoding) => {},
+            r => panic!("Expected UnrecognizedTransferEncoding for {:?}: {:?}", value, r),
+        }
+    }
+}
+
+#[test]
+fn test_connection_options() {
+    let cases = vec![
+        (&b"close"[..], (1, 1), false),
+        (&b"keep-alive"[..], (1, 1), true),
+        (&b"upgrade"[..], (1, 1), true),
+        (&b"Upgrade, Close"[..], (1, 1), false),
+        (&b"close"[..], (1, 0), false),
+        (&b"Keep-Alive"[..], (1, 0), true),
+        (&b"upgrade"[..], (1, 0), false),
+        (&b"keep-alive, close"[..], (1, 0), false),
+    ];
+
+    for (value, version, expected) in cases {
+        let mut options = ConnectionOptions::default();
+        options.on_header(b"Connection", value);
+        assert_eq!(expected, options.keep_alive(version), "{:?}", value);
+    }
+}
+
+#[test]
+fn test_upgrade_requested() {
+    let cases = vec![
+        (vec![(&b"Connection"[..], &b"Upgrade"[..]), (b"Upgrade", b"websocket")], (1, 1), true),
+        (vec![(&b"Connection"[..], &b"keep-alive, upgrade"[..]), (b"upgrade", b"h2c")], (1, 1), true),
+        (vec![(&b"Connection"[..], &b"Upgrade"[..]), (b"Upgrade", b"websocket")], (1, 0), false),
+        (vec![(&b"Upgrade"[..], &b"websocket"[..])], (1, 1), false),
+        (vec![(&b"Connection"[..], &b"Upgrade"[..]), (b"Upgrade", b" , ")], (1, 1), false),
+    ];
+
+    for (headers, version, expected) in cases {
+        let mut options = ConnectionOptions::default();
+        for &(name, value) in headers.iter() {
+            options.on_header(name, value);
+        }
+        assert_eq!(expected, options.upgrade_requested(version), "{:?}", headers);
+    }
+}
+
+#[test]
+fn test_comma_separated() {
+    let elements: Vec<&[u8]> = comma_separated(b" gzip ,, chunked\t").collect();
+    assert_eq!(vec![&b"gzip"[..], &b"chunked"[..]], elements);
+}
@@ -1,49 +1,48 @@
 use errors::IntegerDecodeError;
 
 
-pub fn hex_buf_to_int(buf: &[u8]) -> Result<usize, IntegerDecodeError> {
-    if buf.len() >= 8 {  // TODO: Replace with usize::BITS
-        return Err(IntegerDecodeError::TooLong(buf.len()));
+pub fn hex_buf_to_int(buf: &[u8]) -> Result<u64, IntegerDecodeError> {
+    if buf.len() == 0 {
+        return Err(IntegerDecodeError::Empty);
     }
 
-    let mut size : usize = 0;
+    let mut size : u64 = 0;
     for c in buf {
-        size *= 16;
-        match *c {
-            d @ b'0' ... b'9' => {
-                size += (d - b'0') as usize;
-            },
-            d @ b'A' ... b'F' => {
-                size += (d - b'A' + 10) as usize;
-            },
-            d @ b'a' ... b'f' => {
-                size += (d - b'a' + 10) as usize;
-            },
+        let digit = match *c {
+            d @ b'0' ... b'9' => d - b'0',
+            d @ b'A' ... b'F' => d - b'A' + 10,
+            d @ b'a' ... b'f' => d - b'a' + 10,
             d @ _ => {
                 return Err(IntegerDecodeError::InvalidChar(d))
             },
-        }
+        };
+        size = try!(
+            size.checked_mul(16)
+                .and_then(|s| s.checked_add(digit as u64))
+                .ok_or(IntegerDecodeError::Overflow)
+        );
     }
     Ok(size)
 }
 
-pub fn dec_buf_to_int(buf: &[u8]) -> Result<usize, IntegerDecodeError> {
-    // 2^N > 10^X => N > X log2 (10) > 3.32 X > 3 X
-    if buf.len() >= 8 {
-        return Err(IntegerDecodeError::TooLong(buf.len()));
+pub fn dec_buf_to_int(buf: &[u8]) -> Result<u64, IntegerDecodeError> {
+    if buf.len() == 0 {
+        return Err(IntegerDecodeError::Empty);
     }
 
-    let mut size : usize = 0;
+    let mut size : u64 = 0;
     for c in buf {
-        size *= 10;
-        match *c {
-            d @ b'0' ... b'9' => {
-                size += (d - b'0') as usize;
-            },
+        let digit = match *c {
+            d @ b'0' ... b'9' => d - b'0',
             d @ _ => {
                 return Err(IntegerDecodeError::InvalidChar(d))
             },
-        }
+        };
+        size = try!(
+            size.checked_mul(10)
+                .and_then(|s| s.checked_add(digit as u64))
+                .ok_or(IntegerDecodeError::Overflow)
+        );
     }
     Ok(size)
 }
@@ -53,10 +52,18 @@ fn test_hex() {
     assert_eq!(245, hex_buf_to_int(b"F5").unwrap());
     assert_eq!(245, hex_buf_to_int(b"f5").unwrap());
     assert_eq!(9, hex_buf_to_int(b"9").unwrap());
+    assert_eq!(0x123456789, hex_buf_to_int(b"0123456789").unwrap());
+    assert_eq!(::std::u64::MAX, hex_buf_to_int(b"ffffffffffffffff").unwrap());
+    assert_eq!(1, hex_buf_to_int(b"00000000000000000000001").unwrap());
 
     assert_eq!(
-        Result::Err(IntegerDecodeError::TooLong(10)),
-        hex_buf_to_int(b"0123456789")
+        Result::Err(IntegerDecodeError::Overflow),
+        hex_buf_to_int(b"10000000000000000")
+    );
+
+    assert_eq!(
+        Result::Err(IntegerDecodeError::Empty),
+        hex_buf_to_int(b"")
     );
 
     assert_eq!(
@@ -69,14 +76,29 @@ fn test_hex() {
 fn test_dec() {
     assert_eq!(245, dec_buf_to_int(b"245").unwrap());
     assert_eq!(9, dec_buf_to_int(b"9").unwrap());
+    assert_eq!(123456789, dec_buf_to_int(b"0123456789").unwrap());
+    assert_eq!(5000000000, dec_buf_to_int(b"5000000000").unwrap());
+    assert_eq!(::std::u64::MAX, dec_buf_to_int(b"18446744073709551615").unwrap());
+
+    assert_eq!(
+        Result::Err(IntegerDecodeError::Overflow),
+        dec_buf_to_int(b"18446744073709551616")
+    );
 
     assert_eq!(
-        Result::Err(IntegerDecodeError::TooLong(10)),
-        dec_buf_to_int(b"0123456789")
+        Result::Err(IntegerDecodeError::Empty),
+        dec_buf_to_int(b"")
     );
 
     assert_eq!(
         Result::Err(IntegerDecodeError::InvalidChar(b'X')),
         dec_buf_to_int(b"0125X5")
     );
+
+    for input in [&b"+5"[..], &b"-5"[..], &b" 5"[..], &b"5 "[..]].iter() {
+        match dec_buf_to_int(input) {
+            Err(IntegerDecodeError::InvalidChar(_)) => {},
+            r => panic!("Expected InvalidChar for {:?}: {:?}", input, r),
+        }
+    }
 }
@@ -5,19 +5,37 @@ extern crate nom;
 pub mod integer_decoder;
 pub mod buffered;
 pub mod errors;
+pub mod config;
+pub use config::{ParserConfig, Strictness};
 
 
+mod anomalies;
+pub use anomalies::Anomaly;
+mod diagnose;
+mod headers;
+pub use headers::TransferCoding;
+mod method;
+pub use method::Method;
 mod nom_parsers;
-pub use nom_parsers::{RequestLine, ResponseLine};
+pub use nom_parsers::{RequestLine, ResponseLine, ChunkHeader, ChunkParameter};
+mod resync;
+mod status;
+pub use status::StatusCode;
 
 pub mod parser;
 pub use parser::{
     HttpParserResult,
+    CallbackResult,
     HttpParser,
     ExpectBody,
+    ParseStatus,
     BodyType,
     ParserType,
     HttpMessageCallbacks,
     HttpRequestCallbacks,
     HttpResponseCallbacks,
+    NullCallbacks,
 };
+
+mod events;
+pub use events::HttpEvent;
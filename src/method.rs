@@ -0,0 +1,125 @@
+/// A request method, as per RFC 9110 section 9 and the WebDAV methods from RFC 4918.
+///
+/// Method names are case-sensitive, so e.g. `get` is an extension method rather than GET.
+#[derive(PartialEq,Eq,Debug,Clone,Copy)]
+pub enum Method<'r> {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    Propfind,
+    Proppatch,
+    Mkcol,
+    Copy,
+    Move,
+    Lock,
+    Unlock,
+    /// Any other method.
+    Extension(&'r [u8]),
+}
+
+impl<'r> Method<'r> {
+    /// Looks up a method by its name. The parser only passes names that are valid tokens.
+    pub fn from_bytes(name: &'r [u8]) -> Method<'r> {
+        match name {
+            b"GET" => Method::Get,
+            b"HEAD" => Method::Head,
+            b"POST" => Method::Post,
+            b"PUT" => Method::Put,
+            b"DELETE" => Method::Delete,
+            b"CONNECT" => Method::Connect,
+            b"OPTIONS" => Method::Options,
+            b"TRACE" => Method::Trace,
+            b"PATCH" => Method::Patch,
+            b"PROPFIND" => Method::Propfind,
+            b"PROPPATCH" => Method::Proppatch,
+            b"MKCOL" => Method::Mkcol,
+            b"COPY" => Method::Copy,
+            b"MOVE" => Method::Move,
+            b"LOCK" => Method::Lock,
+            b"UNLOCK" => Method::Unlock,
+            _ => Method::Extension(name),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &'r [u8] {
+        match *self {
+            Method::Get => b"GET",
+            Method::Head => b"HEAD",
+            Method::Post => b"POST",
+            Method::Put => b"PUT",
+            Method::Delete => b"DELETE",
+            Method::Connect => b"CONNECT",
+            Method::Options => b"OPTIONS",
+            Method::Trace => b"TRACE",
+            Method::Patch => b"PATCH",
+            Method::Propfind => b"PROPFIND",
+            Method::Proppatch => b"PROPPATCH",
+            Method::Mkcol => b"MKCOL",
+            Method::Copy => b"COPY",
+            Method::Move => b"MOVE",
+            Method::Lock => b"LOCK",
+            Method::Unlock => b"UNLOCK",
+            Method::Extension(name) => name,
+        }
+    }
+
+    /// Whether the method is read-only, i.e. the client doesn't ask for any change on the
+    /// server. Nothing is assumed about extension methods.
+    pub fn is_safe(&self) -> bool {
+        match *self {
+            Method::Get | Method::Head | Method::Options | Method::Trace | Method::Propfind => true,
+            _ => false,
+        }
+    }
+
+    /// Whether sending the request several times has the same effect as sending it once, so
+    /// it may be retried automatically. Nothing is assumed about extension methods.
+    pub fn is_idempotent(&self) -> bool {
+        if self.is_safe() {
+            return true;
+        }
+        match *self {
+            Method::Put | Method::Delete | Method::Proppatch | Method::Mkcol | Method::Copy
+            | Method::Move | Method::Unlock => true,
+            _ => false,
+        }
+    }
+
+    /// Whether responses to the method may be stored by a cache. Responses to POST may only be
+    /// reused with explicit freshness information and a matching Content-Location, so in
+    /// practice hardly ever are.
+    pub fn is_cacheable(&self) -> bool {
+        match *self {
+            Method::Get | Method::Head | Method::Post => true,
+            _ => false,
+        }
+    }
+}
+
+
+#[test]
+fn test_method() {
+    let methods = [
+        &b"GET"[..], b"HEAD", b"POST", b"PUT", b"DELETE", b"CONNECT", b"OPTIONS", b"TRACE",
+        b"PATCH", b"PROPFIND", b"PROPPATCH", b"MKCOL", b"COPY", b"MOVE", b"LOCK", b"UNLOCK",
+        b"get", b"PURGE",
+    ];
+
+    for name in methods.iter() {
+        assert_eq!(*name, Method::from_bytes(name).as_bytes());
+    }
+
+    assert_eq!(Method::Extension(b"get"), Method::from_bytes(b"get"));
+    assert!(Method::Head.is_safe() && Method::Head.is_idempotent() && Method::Head.is_cacheable());
+    assert!(!Method::Put.is_safe() && Method::Put.is_idempotent() && !Method::Put.is_cacheable());
+    assert!(!Method::Post.is_safe() && !Method::Post.is_idempotent() && Method::Post.is_cacheable());
+    assert!(!Method::Lock.is_idempotent());
+    let purge = Method::Extension(b"PURGE");
+    assert!(!purge.is_safe() && !purge.is_idempotent() && !purge.is_cacheable());
+}
@@ -2,6 +2,10 @@
 use nom::{IResult, Needed, Err, space, digit, is_digit};
 
 use integer_decoder::hex_buf_to_int;
+use method::Method;
+use status::StatusCode;
+
+use std::borrow::Cow;
 
 
 // named!(not_space, take_until_either!(" \t"));
@@ -23,16 +27,50 @@ fn not_space(input: &[u8]) -> IResult<&[u8], &[u8]> {
     IResult::Incomplete(Needed::Size(1))
 }
 
+pub fn is_token_char(chr: u8) -> bool {
+    match chr {
+        33 | 35...39 | 42 | 43 | 45 | 46 | 48...57 | 65...90 | 94...122 | 124 | 126 => true,
+        _ => false,
+    }
+}
+
+/// Whether the byte is whitespace within a line, i.e. a space or a tab.
+pub(crate) fn is_space(chr: u8) -> bool {
+    chr == b' ' || chr == b'\t'
+}
+
+/// The position of the first byte from `pos` on that isn't a space or a tab.
+pub(crate) fn skip_space(input: &[u8], pos: usize) -> usize {
+    input[pos..].iter().position(|c| !is_space(*c)).map_or(input.len(), |p| pos + p)
+}
+
 fn token(input: &[u8]) -> IResult<&[u8], &[u8]> {
     for (idx, chr) in input.iter().enumerate() {
-        match *chr {
-            33 | 35...39 | 42 | 43 | 45 | 48...57 | 65...90 | 94...122 | 124 => continue,
-            _ => return IResult::Done(&input[idx..], &input[..idx]),
+        if !is_token_char(*chr) {
+            return IResult::Done(&input[idx..], &input[..idx]);
         }
     }
     IResult::Incomplete(Needed::Size(1))
 }
 
+/// Like `token`, but the token can't be empty.
+fn token1<'r>(input: &'r [u8]) -> IResult<'r, &'r [u8], &'r [u8]> {
+    match token(input) {
+        IResult::Done(_, o) if o.len() == 0 => IResult::Error(Err::Code(0)),
+        res => res,
+    }
+}
+
+/// A CRLF line ending, without accepting a bare LF.
+fn crlf<'r>(input: &'r [u8]) -> IResult<'r, &'r [u8], &'r [u8]> {
+    match (input.get(0), input.get(1)) {
+        (Some(&b'\r'), Some(&b'\n')) => IResult::Done(&input[2..], &input[..2]),
+        (Some(&b'\r'), None) => IResult::Incomplete(Needed::Size(1)),
+        (None, _) => IResult::Incomplete(Needed::Size(2)),
+        _ => IResult::Error(Err::Code(0)),
+    }
+}
+
 fn hexdigit(input: &[u8]) -> IResult<&[u8], &[u8]> {
     for (idx, chr) in input.iter().enumerate() {
         match *chr {
@@ -65,7 +103,7 @@ fn not_vspace(input: &[u8]) -> IResult<&[u8], &[u8]> {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct RequestLine<'r> {
-    pub method: &'r [u8],
+    pub method: Method<'r>,
     pub path: &'r [u8],
     pub version: (u8, u8),
 }
@@ -73,7 +111,7 @@ pub struct RequestLine<'r> {
 #[derive(Debug, PartialEq, Clone)]
 pub struct ResponseLine<'r> {
     pub version: (u8, u8),
-    pub code: u16,
+    pub code: StatusCode,
     pub phrase: &'r [u8],
 }
 
@@ -101,7 +139,7 @@ named!{
 named!(
     pub request_line <&[u8], RequestLine>,
     chain!(
-        method: not_space   ~
+        method: token1      ~
         space               ~
         path: not_space     ~
         space               ~
@@ -114,7 +152,7 @@ named!(
         tag!("\n")          ,
         || {
             RequestLine{
-                method: method,
+                method: Method::from_bytes(method),
                 path: path,
                 version: (major[0] - b'0', minor[0] - b'0'),
             }
@@ -122,7 +160,63 @@ named!(
     )
 );
 
-fn response_code(input: &[u8]) -> IResult<&[u8], u16> {
+// The strict parsers only accept what RFC 9112 allows senders to send: single spaces between
+// the parts of the start line, no whitespace before a header's colon, no obs-fold and CRLF line
+// endings.
+
+named!{
+    pub response_line_strict<&[u8], ResponseLine>,
+    chain!(
+        tag!("HTTP/")                   ~
+        major: digit                    ~
+        tag!(".")                       ~
+        minor: digit                    ~
+        tag!(" ")                       ~
+        code: response_code             ~
+        phrase: reason_phrase_strict    ~
+        crlf                            ,
+        || {ResponseLine{
+            version: (major[0] - b'0', minor[0] - b'0'),
+            code: code,
+            phrase: phrase
+        }}
+    )
+}
+
+named!(
+    pub request_line_strict <&[u8], RequestLine>,
+    chain!(
+        method: token1      ~
+        tag!(" ")           ~
+        path: not_space     ~
+        tag!(" ")           ~
+        tag!("HTTP/")       ~
+        major: digit        ~
+        tag!(".")           ~
+        minor: digit        ~
+        crlf                ,
+        || {
+            RequestLine{
+                method: Method::from_bytes(method),
+                path: path,
+                version: (major[0] - b'0', minor[0] - b'0'),
+            }
+        }
+    )
+);
+
+// The reason phrase is optional, and so is the space before it when it's missing, as plenty of
+// servers leave both out.
+fn reason_phrase_strict<'r>(input: &'r [u8]) -> IResult<'r, &'r [u8], &'r [u8]> {
+    match input.get(0) {
+        Some(&b' ') => not_vspace(&input[1..]),
+        Some(&b'\r') | Some(&b'\n') => IResult::Done(input, &input[..0]),
+        Some(_) => IResult::Error(Err::Code(0)),
+        None => IResult::Incomplete(Needed::Size(1)),
+    }
+}
+
+fn response_code(input: &[u8]) -> IResult<&[u8], StatusCode> {
     if input.len() < 3 {
         return IResult::Incomplete(Needed::Size(3 - input.len()));
     }
@@ -133,15 +227,17 @@ fn response_code(input: &[u8]) -> IResult<&[u8], u16> {
         }
     }
 
-    IResult::Done(
-        &input[3..],
-        (input[0] - b'0') as u16 * 100u16
+    let code = (input[0] - b'0') as u16 * 100u16
         + (input[1] - b'0') as u16 * 10u16
-        + (input[2] - b'0') as u16
-    )
+        + (input[2] - b'0') as u16;
+    match StatusCode::from_u16(code) {
+        Some(code) => IResult::Done(&input[3..], code),
+        None => IResult::Error(Err::Code(0)),
+    }
 }
 
-// We need this to deal with the insanity of obs-fold
+// We need this to deal with the insanity of obs-fold. The value may be empty, and leaves out
+// any whitespace around it, so what's left starts at the line ending.
 fn take_header_value(buf: &[u8]) -> IResult<&[u8], &[u8]> {
     let mut end_pos = 0;
     let mut idx = 0;
@@ -157,10 +253,12 @@ fn take_header_value(buf: &[u8]) -> IResult<&[u8], &[u8]> {
                         idx += 1;
                         continue;
                     },
-                    _ => if end_pos > 0 {
-                        return IResult::Done(&buf[end_pos..], &buf[..end_pos])
-                    } else {
-                        return IResult::Error(Err::Code(0))
+                    _ => {
+                        let line_end = match idx {
+                            idx if idx >= 2 && buf[idx - 2] == b'\r' => idx - 2,
+                            idx => idx - 1,
+                        };
+                        return IResult::Done(&buf[line_end..], &buf[..end_pos]);
                     },
                 }
             },
@@ -175,7 +273,7 @@ fn take_header_value(buf: &[u8]) -> IResult<&[u8], &[u8]> {
 named!(
     pub header <&[u8], (&[u8], &[u8])>,
     chain!(
-        name: token                 ~
+        name: token1                ~
         space?                      ~
         tag!(":")                   ~
         space?                      ~
@@ -186,25 +284,61 @@ named!(
     )
 );
 
+fn take_header_value_strict<'r>(buf: &'r [u8]) -> IResult<'r, &'r [u8], &'r [u8]> {
+    let mut end_pos = 0;
+    for (idx, chr) in buf.iter().enumerate() {
+        match *chr {
+            b'\r' | b'\n' => return IResult::Done(&buf[idx..], &buf[..end_pos]),
+            b' ' | b'\t' => {},
+            _ => end_pos = idx + 1,
+        }
+    }
+    IResult::Incomplete(Needed::Size(1))
+}
+
+named!(
+    pub header_strict <&[u8], (&[u8], &[u8])>,
+    chain!(
+        name: token1                        ~
+        tag!(":")                           ~
+        space?                              ~
+        value: take_header_value_strict     ~
+        crlf                                ,
+        || {(name, value)}
+    )
+);
+
 
-fn quoted_string_or_token(buf: &[u8]) -> IResult<&[u8], &[u8]> {
+fn quoted_string_or_token(buf: &[u8]) -> IResult<&[u8], Cow<[u8]>> {
     if buf.len() == 0 {
         return IResult::Incomplete(Needed::Size(1));
     }
 
     if buf[0] != b'"' {
-        return not_space_or_semicolon(buf);
+        return match not_space_or_semicolon(buf) {
+            IResult::Done(i, o) => IResult::Done(i, Cow::Borrowed(o)),
+            IResult::Incomplete(n) => IResult::Incomplete(n),
+            IResult::Error(e) => IResult::Error(e),
+        };
     }
 
+    let mut escaped = false;
     let mut idx = 1;
     while idx < buf.len() {
         match buf[idx] {
             b'\\' => {
+                escaped = true;
                 idx += 2;
                 continue;
             },
             b'"' => {
-                return IResult::Done(&buf[idx+1..], &buf[1..idx]);
+                let value = &buf[1..idx];
+                let value = if escaped {
+                    Cow::Owned(unescape_quoted_string(value))
+                } else {
+                    Cow::Borrowed(value)
+                };
+                return IResult::Done(&buf[idx+1..], value);
             }
             b' ' | b'\t' | 0x21 | 0x23...0x5b | 0x5D...0x7E | 0x80...0xFF => {
                 idx += 1;
@@ -218,8 +352,25 @@ fn quoted_string_or_token(buf: &[u8]) -> IResult<&[u8], &[u8]> {
     IResult::Incomplete(Needed::Size(1))
 }
 
+// Strips the backslashes from any quoted-pairs. Expects the contents of the quoted-string
+// without the surrounding DQUOTEs.
+fn unescape_quoted_string(buf: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(buf.len());
+    let mut iter = buf.iter();
+    while let Some(&chr) = iter.next() {
+        if chr == b'\\' {
+            if let Some(&escaped) = iter.next() {
+                value.push(escaped);
+            }
+        } else {
+            value.push(chr);
+        }
+    }
+    value
+}
+
 named!(
-    chunk_parameter_value,
+    chunk_parameter_value<&[u8], Cow<[u8]> >,
     chain!(
         tag!("=")   ~
         space?      ~
@@ -237,8 +388,12 @@ named!(
     )
 );
 
+pub fn empty_line_strict<'r>(input: &'r [u8]) -> IResult<'r, &'r [u8], &'r [u8]> {
+    crlf(input)
+}
+
 named!(
-    chunk_parameter<&[u8], (&[u8], Option<&[u8]>)>,
+    chunk_parameter<&[u8], ChunkParameter>,
     chain!(
         tag!(";")                           ~
         space?                              ~
@@ -250,7 +405,7 @@ named!(
     )
 );
 
-fn many_chunk_params(input: &[u8]) -> IResult<&[u8], Vec<(&[u8], Option<&[u8]>)>> {
+fn many_chunk_params(input: &[u8]) -> IResult<&[u8], Vec<ChunkParameter>> {
     let mut begin = 0;
     let mut remaining = input.len();
     let mut res = Vec::new();
@@ -275,10 +430,15 @@ fn many_chunk_params(input: &[u8]) -> IResult<&[u8], Vec<(&[u8], Option<&[u8]>)>
     IResult::Done(&input[begin..], res)
 }
 
-#[derive(Debug, PartialEq)]
+/// A chunk extension, i.e. a name and an optional value.
+///
+/// Values that were sent as quoted-strings have been unescaped.
+pub type ChunkParameter<'r> = (&'r [u8], Option<Cow<'r, [u8]>>);
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct ChunkHeader<'r> {
-    pub parameters: Vec<(&'r[u8], Option<&'r[u8]>)>,
-    pub size: usize,
+    pub parameters: Vec<ChunkParameter<'r>>,
+    pub size: u64,
 }
 
 named!(
@@ -297,6 +457,21 @@ named!(
     )
 );
 
+named!(
+    pub chunk_parser_strict <&[u8], ChunkHeader>,
+    chain!(
+        size: map_res!(
+            hexdigit,
+            hex_buf_to_int
+        )                                       ~
+        space?                                  ~
+        values: many_chunk_params               ~
+        space?                                  ~
+        crlf                                    ,
+        || ChunkHeader{parameters: values, size: size}
+    )
+);
+
 
 // ***************************************
 // **************** TESTS ****************
@@ -321,21 +496,24 @@ macro_rules! test_parser {
 
 test_parser!(
     chunk_parameter_value,
-    b"foo" => [
+    &b"foo"[..] => [
         test_chunk_val_1 => b"= foo ",
         test_chunk_val_2 => b"=foo\n",
         test_chunk_val_3 => b"=foo;",
         test_chunk_val_4 => b"=\"foo\";",
     ],
-    b"wibble wobble" => [
+    &b"wibble wobble"[..] => [
         test_chunk_val_5 => b"=\"wibble wobble\";",
     ],
+    &b"say \"hi\"\\"[..] => [
+        test_chunk_val_escaped => b"=\"say \\\"hi\\\"\\\\\";",
+    ],
 );
 
 
 test_parser!(
     chunk_parameter,
-    (&b"foo"[..], Option::Some(&b"bar"[..])) => [
+    (&b"foo"[..], Option::Some(Cow::Borrowed(&b"bar"[..]))) => [
         test_chunk_1 => b";foo = bar ",
         test_chunk_2 => b";foo=bar\n",
         test_chunk_3 => b";foo=bar;",
@@ -354,7 +532,7 @@ test_parser!(
         test_chunk_header_1 => b"F8\n",
         test_chunk_header_2 => b"F8 \r\n",
     ],
-    ChunkHeader{parameters: vec![(b"name", Option::Some(b"val"))], size: 248} => [
+    ChunkHeader{parameters: vec![(b"name", Option::Some(Cow::Borrowed(&b"val"[..])))], size: 248} => [
         test_chunk_header_3 => b"F8;name=val\n",
         test_chunk_header_4 => b"F8 ; name = val \r\n",
     ],
@@ -362,7 +540,7 @@ test_parser!(
 
 test_parser!(
     request_line,
-    RequestLine{method: b"GET", path: b"/test_url/", version: (1, 0)} => [
+    RequestLine{method: Method::Get, path: b"/test_url/", version: (1, 0)} => [
         test_re_l_1 => b"GET /test_url/ HTTP/1.0\r\n",
         test_re_l_2 => b"GET /test_url/ HTTP/1.0\n",
         test_re_l_3 => b"GET  /test_url/ \t HTTP/1.0\t  \n",
@@ -371,10 +549,14 @@ test_parser!(
 
 test_parser!(
     response_line,
-    ResponseLine{version: (1, 0), code: 500u16, phrase: b"Internal Server Error"} => [
+    ResponseLine{version: (1, 0), code: StatusCode::from_u16(500).unwrap(), phrase: b"Internal Server Error"} => [
         test_res_l_1 => b"HTTP/1.0 500 Internal Server Error\r\n",
         test_res_l_2 => b"HTTP/1.0 500 Internal Server Error\n",
         test_res_l_3 => b"HTTP/1.0  500  \t Internal Server Error\r\n",
+    ],
+    ResponseLine{version: (1, 1), code: StatusCode::from_u16(204).unwrap(), phrase: b""} => [
+        test_res_l_no_phrase_1 => b"HTTP/1.1 204\r\n",
+        test_res_l_no_phrase_2 => b"HTTP/1.1 204 \n",
     ]
 );
 
@@ -389,11 +571,80 @@ test_parser!(
     (&b"Content-Length"[..], &b"test\r\n and\r\n another"[..]) => [
         test_header_obs => b"Content-Length: test\r\n and\r\n another\r\n\r\n",
     ],
+    (&b"X-Empty"[..], &b""[..]) => [
+        test_header_empty_1 => b"X-Empty:\r\nfoo...",
+        test_header_empty_2 => b"X-Empty: \t \nfoo...",
+    ],
+    (&b"X-Padded"[..], &b"value"[..]) => [
+        test_header_padded => b"X-Padded:  value \t\r\nfoo...",
+    ],
+);
+
+test_parser!(
+    request_line_strict,
+    RequestLine{method: Method::Get, path: b"/test_url/", version: (1, 0)} => [
+        test_re_l_strict => b"GET /test_url/ HTTP/1.0\r\n",
+    ]
 );
 
+test_parser!(
+    response_line_strict,
+    ResponseLine{version: (1, 1), code: StatusCode::from_u16(500).unwrap(), phrase: b"Internal Server Error"} => [
+        test_res_l_strict => b"HTTP/1.1 500 Internal Server Error\r\n",
+    ],
+    ResponseLine{version: (1, 1), code: StatusCode::from_u16(204).unwrap(), phrase: b""} => [
+        test_res_l_strict_no_phrase_1 => b"HTTP/1.1 204 \r\n",
+        test_res_l_strict_no_phrase_2 => b"HTTP/1.1 204\r\n",
+    ]
+);
+
+test_parser!(
+    header_strict,
+    (&b"Content-Length"[..], &b"52"[..]) => [
+        test_header_strict_1 => b"Content-Length: 52\r\nfoo...",
+        test_header_strict_2 => b"Content-Length:52 \r\nfoo...",
+    ],
+    (&b"X-Empty"[..], &b""[..]) => [
+        test_header_strict_empty => b"X-Empty:\r\nfoo...",
+    ],
+);
+
+#[test]
+fn test_strict_rejects() {
+    let request_lines = [
+        &b"GET  /test_url/ HTTP/1.0\r\n"[..],
+        &b"GET /test_url/\tHTTP/1.0\r\n"[..],
+        &b"GET /test_url/ HTTP/1.0 \r\n"[..],
+        &b"GET /test_url/ HTTP/1.0\nHost"[..],
+    ];
+    for i in request_lines.iter() {
+        match request_line_strict(i) {
+            IResult::Error(_) => {},
+            d@ _ => panic!("Not rejected: {:?}: {:?}", String::from_utf8_lossy(i), d)
+        }
+    }
+
+    let headers = [
+        &b"Content-Length : 52\r\nfoo..."[..],
+        &b"Content-Length: 52\nfoo..."[..],
+        &b" and more\r\nfoo..."[..],
+    ];
+    for i in headers.iter() {
+        match header_strict(i) {
+            IResult::Error(_) => {},
+            d@ _ => panic!("Not rejected: {:?}: {:?}", String::from_utf8_lossy(i), d)
+        }
+    }
+
+    match chunk_parser_strict(b"F8\nabc") {
+        IResult::Error(_) => {},
+        d@ _ => panic!("Not rejected: {:?}", d)
+    }
+}
+
 test_parser!(
     response_code,
-    200 => [
+    StatusCode::from_u16(200).unwrap() => [
         test_response_code_1 => b"200",
         test_response_code_2 => b"200 ",
     ],
@@ -499,3 +750,23 @@ fn test_response_line_incomplete() {
         }
     }
 }
+
+
+#[test]
+fn test_header_lenient_accepts_strict() {
+    let cases = [
+        &b"X-Empty:\r\nY"[..],
+        &b"X-Empty:  \r\nY"[..],
+        &b"X-Padded: value \t\r\nY"[..],
+        &b"X-Folded: a\r\n \r\nY"[..],
+        &b": value\r\nY"[..],
+    ];
+
+    for i in cases.iter() {
+        match (header(i), header_strict(i)) {
+            (IResult::Done(_, lenient), IResult::Done(_, strict)) => assert_eq!(strict, lenient),
+            (IResult::Error(_), IResult::Error(_)) => {},
+            d@ _ => panic!("Parsers disagree: {:?}: {:?}", String::from_utf8_lossy(i), d)
+        }
+    }
+}
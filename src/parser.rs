@@ -1,15 +1,27 @@
 
-use std::ascii::AsciiExt;
+use std::cmp;
+use std::collections::VecDeque;
 
 
+use anomalies::{self, Anomaly};
+use config::{ParserConfig, Strictness};
+use diagnose;
 use errors::*;
-use integer_decoder;
+use events::HttpEvent;
+use method::Method;
+use status::StatusCode;
+use headers::{self, ConnectionOptions, Framing, TransferCoding};
 
 use nom_parsers;
-use nom_parsers::{RequestLine, ResponseLine};
+use nom_parsers::{RequestLine, ResponseLine, ChunkHeader};
+use resync;
 use nom::{IResult};
 
 pub type HttpParserResult<T> = Result<T, HttpParserError>;
+pub type CallbackResult<T> = Result<T, CallbackError>;
+
+/// The most hex digits a chunk size may have, leaving plenty of room for leading zeros.
+const MAX_CHUNK_SIZE_LENGTH: usize = 32;
 
 
 #[derive(PartialEq,Eq,Debug,Clone,Copy)]
@@ -18,17 +30,36 @@ enum ParserState {
     Headers,
     HeaderEnd,
     Body(BodyTypeState),
+    Trailers,
+    TrailerEnd,
     Done,
+    Upgraded,
+    /// Skipping ahead to the next start line after an error.
+    Resync,
+}
+
+impl ParserState {
+    fn phase(&self) -> ParserPhase {
+        match *self {
+            ParserState::FirstLine | ParserState::Resync => ParserPhase::StartLine,
+            ParserState::Headers | ParserState::HeaderEnd => ParserPhase::Headers,
+            ParserState::Body(BodyTypeState::Chunked(ChunkedState::Data(_))) => ParserPhase::Body,
+            ParserState::Body(BodyTypeState::Chunked(_)) => ParserPhase::ChunkHeader,
+            ParserState::Body(_) => ParserPhase::Body,
+            ParserState::Trailers | ParserState::TrailerEnd => ParserPhase::Trailers,
+            ParserState::Done | ParserState::Upgraded => ParserPhase::Finished,
+        }
+    }
 }
 
 #[derive(PartialEq,Eq,Debug,Clone,Copy)]
-enum ChunkedState { Header, Data(usize), DataEnd }
+enum ChunkedState { Header, Data(u64), DataEnd }
 
 #[derive(PartialEq,Eq,Debug,Clone,Copy)]
-enum BodyTypeState { Lenth(usize), Chunked(ChunkedState), EOF, NoBody }
+enum BodyTypeState { Lenth(u64), Chunked(ChunkedState), EOF, NoBody }
 
 #[derive(PartialEq,Eq,Debug,Clone,Copy)]
-pub enum BodyType { Length(usize), Chunked, EOF, NoBody }
+pub enum BodyType { Length(u64), Chunked, EOF, NoBody }
 
 enum BufferState { Ready(ParserState), Incomplete, }
 
@@ -37,37 +68,204 @@ struct ParserReturn<'r> (&'r [u8], BufferState);
 #[derive(PartialEq,Eq,Debug,Clone,Copy)]
 pub enum ExpectBody { Maybe, No }
 
+/// Why `parse_request`, `parse_response` or `parse` returned, along with the input that wasn't
+/// consumed.
+#[derive(PartialEq,Eq,Debug,Clone,Copy)]
+pub enum ParseStatus {
+    /// A message ended, or was abandoned after an error when recovering from errors. The rest
+    /// of the input may hold the next message, so should be passed in again.
+    Complete,
+    /// The input ran out. Whatever is left is part of a line or chunk, and should be passed in
+    /// again along with more input.
+    NeedMore,
+    /// A callback paused the parser, see `HttpParser::pause`.
+    Paused,
+    /// The connection switched to another protocol, and the rest of the input belongs to it.
+    /// See `HttpMessageCallbacks::on_upgrade`.
+    Upgraded,
+}
+
+/// The kind of request that a response is a response to, as far as framing is concerned.
+#[derive(PartialEq,Eq,Debug,Clone,Copy)]
+enum RequestKind { Head, Connect, Other }
+
+impl RequestKind {
+    fn from_method(method: Method) -> RequestKind {
+        match method {
+            Method::Head => RequestKind::Head,
+            Method::Connect => RequestKind::Connect,
+            _ => RequestKind::Other,
+        }
+    }
+}
+
 
+/// The callbacks made while parsing a message.
+///
+/// Any callback can stop the parser by returning an error, which is then returned from
+/// `parse_request`/`parse_response` as `ErrorKind::Callback`. The input that caused the
+/// callback is treated as consumed, so the parser can't carry on with the stream afterwards.
 pub trait HttpMessageCallbacks<'r> {
-    fn on_header(&mut self, parser: &mut HttpParser, name: &'r [u8], value: &'r [u8]);
-    fn on_headers_finished(&mut self, parser: &mut HttpParser, body_type: BodyType) -> ExpectBody;
-    fn on_chunk(&mut self, parser: &mut HttpParser, data: &'r [u8]);
-    fn on_end(&mut self, parser: &mut HttpParser);
+    /// Called when the first byte of a message is received, before any other callback for the
+    /// message. `HttpParser::message_sequence` and `HttpParser::message_offset` identify the
+    /// message from here until the next message begins.
+    fn on_message_begin(&mut self, _parser: &mut HttpParser) -> CallbackResult<()> {
+        Ok(())
+    }
+    fn on_header(&mut self, _parser: &mut HttpParser, _name: &'r [u8], _value: &'r [u8])
+    -> CallbackResult<()> {
+        Ok(())
+    }
+    fn on_headers_finished(&mut self, _parser: &mut HttpParser, _body_type: BodyType)
+    -> CallbackResult<ExpectBody> {
+        Ok(ExpectBody::Maybe)
+    }
+    /// Called with the size and extensions of each chunk of a chunked body, including the
+    /// final zero sized chunk.
+    fn on_chunk_header(&mut self, _parser: &mut HttpParser, _header: ChunkHeader<'r>)
+    -> CallbackResult<()> {
+        Ok(())
+    }
+    fn on_chunk(&mut self, _parser: &mut HttpParser, _data: &'r [u8]) -> CallbackResult<()> {
+        Ok(())
+    }
+    /// Called for each trailer field after the final chunk of a chunked body.
+    fn on_trailer(&mut self, _parser: &mut HttpParser, _name: &'r [u8], _value: &'r [u8])
+    -> CallbackResult<()> {
+        Ok(())
+    }
+    /// Called after the trailers of a chunked body, even if there weren't any.
+    fn on_trailers_finished(&mut self, _parser: &mut HttpParser) -> CallbackResult<()> {
+        Ok(())
+    }
+    fn on_end(&mut self, _parser: &mut HttpParser) -> CallbackResult<()> {
+        Ok(())
+    }
+    /// Called when the parser accepts something questionable, with the offset of it in the
+    /// stream. This is called before the callback for the line it's on, if there is one.
+    fn on_anomaly(&mut self, _parser: &mut HttpParser, _kind: Anomaly, _offset: u64)
+    -> CallbackResult<()> {
+        Ok(())
+    }
+    /// Called with each error when the parser is set to recover from errors, see
+    /// `ParserConfig::recover_from_errors`. The current message is abandoned.
+    fn on_error(&mut self, _parser: &mut HttpParser, _error: HttpParserError) -> CallbackResult<()> {
+        Ok(())
+    }
+    /// Called when the parser finds the start of a message after an error, with the number of
+    /// bytes it skipped from where the error was.
+    fn on_resync(&mut self, _parser: &mut HttpParser, _skipped: u64) -> CallbackResult<()> {
+        Ok(())
+    }
+    /// Called after `on_end` when the connection switches to another protocol, i.e. after a
+    /// CONNECT request, a request with an Upgrade header and the upgrade connection option, a
+    /// 2xx response to a CONNECT request or a 101 response. The parser then stops consuming
+    /// input, and returns everything after the message untouched.
+    fn on_upgrade(&mut self, _parser: &mut HttpParser) -> CallbackResult<()> {
+        Ok(())
+    }
 }
 
 pub trait HttpRequestCallbacks<'r> : HttpMessageCallbacks<'r> {
-    fn on_request_line(&mut self, parser: &mut HttpParser, request: RequestLine<'r>);
+    fn on_request_line(&mut self, _parser: &mut HttpParser, _request: RequestLine<'r>)
+    -> CallbackResult<()> {
+        Ok(())
+    }
 }
 
 pub trait HttpResponseCallbacks<'r>: HttpMessageCallbacks<'r> {
-    fn on_response_line(&mut self, parser: &mut HttpParser, response: ResponseLine<'r>);
+    fn on_response_line(&mut self, _parser: &mut HttpParser, _response: ResponseLine<'r>)
+    -> CallbackResult<()> {
+        Ok(())
+    }
 }
 
 
-pub enum ParserType { Request, Response, }
+/// Callbacks that ignore everything, for when the parser is only used to find where messages
+/// start and end, e.g. via `HttpParser::message_sequence`.
+#[derive(PartialEq,Eq,Debug,Clone,Copy,Default)]
+pub struct NullCallbacks;
+
+impl <'r> HttpMessageCallbacks<'r> for NullCallbacks {}
+impl <'r> HttpRequestCallbacks<'r> for NullCallbacks {}
+impl <'r> HttpResponseCallbacks<'r> for NullCallbacks {}
+
+
+/// The type of messages a parser handles. `Both` works out whether each message is a request
+/// or a response from its first line, see `HttpParser::parse`.
+#[derive(PartialEq,Eq,Debug,Clone,Copy)]
+pub enum ParserType { Request, Response, Both, }
+
+impl ParserType {
+    /// The body type of a message whose headers don't say.
+    fn default_body_type(self) -> BodyType {
+        match self {
+            ParserType::Request | ParserType::Both => BodyType::NoBody,
+            ParserType::Response => BodyType::EOF,
+        }
+    }
+}
+
+/// Works out whether a message is a request or a response from the start of its first line.
+/// Returns None if there isn't enough input to tell yet.
+fn detect_message_type(input: &[u8]) -> Option<ParserType> {
+    let prefix = b"HTTP/";
+    let len = cmp::min(input.len(), prefix.len());
+    if input[..len] != prefix[..len] {
+        Some(ParserType::Request)
+    } else if len == prefix.len() {
+        Some(ParserType::Response)
+    } else {
+        None
+    }
+}
 
 /// The HttpParser object.
 ///
 /// This stores the current state of the parsing of a stream of bytes. Each stream of bytes
 /// has its own parser.
 ///
-/// Each HttpParser can only process either requests or responses, not both.
+/// Each HttpParser processes either requests or responses, or with `ParserType::Both` works out
+/// which each message is as it goes.
 pub struct HttpParser {
     pub body_type: BodyType,
     current_state: ParserState,
-    body_finished: bool,
     expect_body: ExpectBody,
     parser_type: ParserType,
+    /// The type of the current message, `Both` until it's known.
+    message_type: ParserType,
+    paused: bool,
+    skip_body: bool,
+    message_started: bool,
+    /// Whether a message ended during the current call to `parse_request`/`parse_response`.
+    message_ended: bool,
+    messages_begun: u64,
+    message_offset: u64,
+    bytes_consumed: u64,
+    /// The offset in the stream of the input handed to the current parsing step.
+    step_offset: u64,
+    /// While resyncing, whether the input continues from the start of a line.
+    resync_at_line_start: bool,
+    /// The bytes skipped so far while resyncing.
+    bytes_skipped: u64,
+    header_scan_offset: usize,
+    config: ParserConfig,
+    header_count: usize,
+    headers_size: usize,
+    trailer_count: usize,
+    body_size: u64,
+    framing: Framing,
+    request_kinds: VecDeque<RequestKind>,
+    response_to: RequestKind,
+    status_code: Option<StatusCode>,
+    upgrade: bool,
+    version: (u8, u8),
+    connection: ConnectionOptions,
+    keep_alive: bool,
+    expect_continue: bool,
+    announced_trailers: Vec<Vec<u8>>,
+    /// Events for `next_event` that came after the one it returned.
+    pub(crate) pending_events: VecDeque<HttpEvent<'static>>,
 }
 
 impl HttpParser {
@@ -75,28 +273,215 @@ impl HttpParser {
     ///
     /// Must be told whether the HttpParser will be used to parse requests or responses.
     pub fn new(parser_type: ParserType) -> HttpParser {
+        HttpParser::with_config(parser_type, ParserConfig::default())
+    }
+
+    /// Constructs a new HttpParser that uses the given configuration, e.g. to limit the size
+    /// of messages.
+    pub fn with_config(parser_type: ParserType, config: ParserConfig) -> HttpParser {
         HttpParser {
             current_state: ParserState::FirstLine,
-            body_type: match parser_type {
-                ParserType::Request => BodyType::NoBody,
-                ParserType::Response => BodyType::EOF,
-            },
-            body_finished: false,
+            body_type: parser_type.default_body_type(),
             parser_type: parser_type,
+            message_type: parser_type,
             expect_body: ExpectBody::Maybe,
+            paused: false,
+            skip_body: false,
+            message_started: false,
+            message_ended: false,
+            messages_begun: 0,
+            message_offset: 0,
+            bytes_consumed: 0,
+            step_offset: 0,
+            resync_at_line_start: false,
+            bytes_skipped: 0,
+            header_scan_offset: 0,
+            config: config,
+            header_count: 0,
+            headers_size: 0,
+            trailer_count: 0,
+            body_size: 0,
+            framing: Framing::default(),
+            request_kinds: VecDeque::new(),
+            response_to: RequestKind::Other,
+            status_code: None,
+            upgrade: false,
+            version: (1, 1),
+            connection: ConnectionOptions::default(),
+            keep_alive: true,
+            expect_continue: false,
+            announced_trailers: Vec::new(),
+            pending_events: VecDeque::new(),
+        }
+    }
+
+    /// Pauses the parser.
+    ///
+    /// Intended to be called from within a callback. Once the callback returns the parser stops
+    /// and `parse_request`/`parse_response` return the input that has not yet been consumed,
+    /// along with `ParseStatus::Paused`.
+    /// While paused the parser will not consume any input, until `resume` is called.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resumes a paused parser. The next call to `parse_request`/`parse_response` carries on
+    /// from where the parser was paused.
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The transfer codings applied to the body of the current message, from all of its
+    /// Transfer-Encoding headers, in the order in which they were applied.
+    pub fn transfer_codings(&self) -> &[TransferCoding] {
+        self.framing.transfer_codings()
+    }
+
+    /// Tells a response parser the method of a request that was sent on the connection.
+    ///
+    /// Whether a response has a body depends on the request, e.g. responses to HEAD requests
+    /// never do. This should be called once for each request, in the order the requests were
+    /// sent, and each final (i.e. non 1xx) response is matched up with the next method. If it
+    /// isn't called the response is assumed not to be to a HEAD request.
+    pub fn push_request_method(&mut self, method: Method) {
+        self.request_kinds.push_back(RequestKind::from_method(method));
+    }
+
+    /// Whether the connection has switched to another protocol, see
+    /// `HttpMessageCallbacks::on_upgrade`. Once upgraded the parser no longer consumes any input.
+    pub fn is_upgraded(&self) -> bool {
+        self.current_state == ParserState::Upgraded
+    }
+
+    /// Whether the connection may be reused for another message after the current one.
+    ///
+    /// This is known once the headers of a message have been parsed, and stays valid until the
+    /// headers of the next message have been parsed. It takes into account the HTTP version,
+    /// any Connection header and whether the body is delimited by the connection closing.
+    pub fn should_keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    /// Whether the current request has an `Expect: 100-continue` header, i.e. the client is
+    /// waiting for a `100 Continue` response before it sends the body.
+    ///
+    /// This is known by the time `on_headers_finished` is called. The application can then
+    /// either send `100 Continue` and carry on feeding the body to the parser as normal, or
+    /// reject the request with a final response without reading the body. To reject it either
+    /// return `ExpectBody::No` from `on_headers_finished`, after which the connection can't be
+    /// reused as the client may send the body anyway, or call `skip_body` so that a body that is
+    /// sent anyway is discarded.
+    ///
+    /// Always false for responses and for HTTP/1.0 requests.
+    pub fn expects_continue(&self) -> bool {
+        self.expect_continue
+    }
+
+    /// The number of messages before the current one on this stream, i.e. 0 for the first
+    /// message, 1 for the second and so on.
+    pub fn message_sequence(&self) -> u64 {
+        self.messages_begun.saturating_sub(1)
+    }
+
+    /// The offset of the first byte of the current message from the start of the stream.
+    pub fn message_offset(&self) -> u64 {
+        self.message_offset
+    }
+
+    /// Tells the parser that the stream has ended, e.g. because the connection was closed.
+    ///
+    /// This completes a message whose body is delimited by the end of the stream, calling
+    /// `on_end`. Returns `ErrorKind::UnexpectedEof` if the stream ended part way through a
+    /// message, e.g. in the middle of the headers or before the full body was received.
+    pub fn finish<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T) -> HttpParserResult<()> {
+        match self.current_state {
+            ParserState::FirstLine => {
+                if self.message_started {
+                    return Err(self.unexpected_eof());
+                }
+            },
+            ParserState::Body(BodyTypeState::EOF)
+            | ParserState::Body(BodyTypeState::NoBody)
+            | ParserState::Body(BodyTypeState::Lenth(0))
+            | ParserState::Done => {
+                try!(self.end_message(cb));
+            },
+            ParserState::Upgraded | ParserState::Resync => {},
+            ParserState::Headers
+            | ParserState::HeaderEnd
+            | ParserState::Body(_)
+            | ParserState::Trailers
+            | ParserState::TrailerEnd => return Err(self.unexpected_eof()),
         }
+        Ok(())
+    }
+
+    fn unexpected_eof(&self) -> HttpParserError {
+        HttpParserError::from(ErrorKind::UnexpectedEof)
+            .shift(self.bytes_consumed)
+            .in_phase(self.current_state.phase())
+    }
+
+    /// Discards the rest of the body of the current message.
+    ///
+    /// May be called from any callback. The parser continues to follow the framing of the body,
+    /// so that the end of the message is still found, but no longer calls `on_chunk`. All other
+    /// callbacks, including `on_end`, are called as normal. Only applies to the current message.
+    pub fn skip_body(&mut self) {
+        self.skip_body = true;
     }
 
     pub fn parse_request<'r, T: HttpRequestCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
+    -> HttpParserResult<(&'r [u8], ParseStatus)> {
+        self.message_ended = false;
+        let input = try!(self.resync(cb, input));
+        if self.current_state == ParserState::Resync {
+            return Ok((input, self.parse_status()));
+        }
+
+        let consumed = self.bytes_consumed;
+        let remaining = match self.parse_request_input(cb, input) {
+            Ok(remaining) => {
+                self.bytes_consumed += (input.len() - remaining.len()) as u64;
+                remaining
+            },
+            Err(e) => try!(self.recover(cb, input, e.shift(consumed))),
+        };
+        Ok((remaining, self.parse_status()))
+    }
+
+    fn parse_request_input<'r, T: HttpRequestCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
     -> HttpParserResult<&'r [u8]> {
+        if self.paused {
+            return Ok(input);
+        }
+
         let mut curr_input = input;
         if let ParserState::FirstLine = self.current_state {
+            if self.message_type == ParserType::Both {
+                self.set_message_type(ParserType::Request);
+            }
+            if curr_input.len() > 0 && !self.message_started {
+                try!(self.begin_message(cb));
+                if self.paused {
+                    return Ok(curr_input);
+                }
+            }
+
+            self.step_offset = self.bytes_consumed;
             let res = try!(self.parse_request_line(cb, curr_input));
             curr_input = res.0;
 
             match res.1 {
                 BufferState::Ready(next_state) => {
                     self.current_state = next_state;
+                    if self.paused {
+                        return Ok(curr_input);
+                    }
                 },
                 BufferState::Incomplete => {
                     return Ok(curr_input);
@@ -104,19 +489,58 @@ impl HttpParser {
             }
         }
 
-        self.parse_http(cb, curr_input)
+        let offset = (input.len() - curr_input.len()) as u64;
+        self.step_offset = self.bytes_consumed + offset;
+        self.parse_http(cb, curr_input).map_err(|e| e.shift(offset))
     }
 
     pub fn parse_response<'r, T: HttpResponseCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
+    -> HttpParserResult<(&'r [u8], ParseStatus)> {
+        self.message_ended = false;
+        let input = try!(self.resync(cb, input));
+        if self.current_state == ParserState::Resync {
+            return Ok((input, self.parse_status()));
+        }
+
+        let consumed = self.bytes_consumed;
+        let remaining = match self.parse_response_input(cb, input) {
+            Ok(remaining) => {
+                self.bytes_consumed += (input.len() - remaining.len()) as u64;
+                remaining
+            },
+            Err(e) => try!(self.recover(cb, input, e.shift(consumed))),
+        };
+        Ok((remaining, self.parse_status()))
+    }
+
+    fn parse_response_input<'r, T: HttpResponseCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
     -> HttpParserResult<&'r [u8]> {
+        if self.paused {
+            return Ok(input);
+        }
+
         let mut curr_input = input;
         if let ParserState::FirstLine = self.current_state {
+            if self.message_type == ParserType::Both {
+                self.set_message_type(ParserType::Response);
+            }
+            if curr_input.len() > 0 && !self.message_started {
+                try!(self.begin_message(cb));
+                if self.paused {
+                    return Ok(curr_input);
+                }
+            }
+
+            self.step_offset = self.bytes_consumed;
             let res = try!(self.parse_response_line(cb, curr_input));
             curr_input = res.0;
 
             match res.1 {
                 BufferState::Ready(next_state) => {
                     self.current_state = next_state;
+                    if self.paused {
+                        return Ok(curr_input);
+                    }
                 },
                 BufferState::Incomplete => {
                     return Ok(curr_input);
@@ -124,29 +548,77 @@ impl HttpParser {
             }
         }
 
-        self.parse_http(cb, curr_input)
+        let offset = (input.len() - curr_input.len()) as u64;
+        self.step_offset = self.bytes_consumed + offset;
+        self.parse_http(cb, curr_input).map_err(|e| e.shift(offset))
+    }
+
+    /// Parses requests or responses, depending on the type of the parser.
+    ///
+    /// With `ParserType::Both` the type of each message is worked out from the start of its first
+    /// line, as only responses start with `HTTP/`, and the parser goes back to working it out
+    /// once the message is finished. The input isn't consumed until there's enough to tell.
+    pub fn parse<'r, T>(&mut self, cb: &mut T, input: &'r [u8])
+    -> HttpParserResult<(&'r [u8], ParseStatus)>
+    where T: HttpRequestCallbacks<'r> + HttpResponseCallbacks<'r> {
+        self.message_ended = false;
+        let input = try!(self.resync(cb, input));
+        if self.message_type == ParserType::Both && self.current_state == ParserState::FirstLine {
+            // The message starts with its first byte, even if that isn't enough to tell its type,
+            // so that `finish` knows it was cut short.
+            if input.len() > 0 && !self.message_started && !self.paused {
+                try!(self.begin_message(cb));
+            }
+            match detect_message_type(input) {
+                Some(message_type) => self.set_message_type(message_type),
+                None => return Ok((input, self.parse_status())),
+            }
+        }
+
+        match self.message_type {
+            ParserType::Request => self.parse_request(cb, input),
+            ParserType::Response => self.parse_response(cb, input),
+            // Only once upgraded, as there are no more messages to work out the type of.
+            ParserType::Both => Ok((input, self.parse_status())),
+        }
+    }
+
+    /// Why the parser stopped, at the end of a call to `parse_request`/`parse_response`.
+    fn parse_status(&self) -> ParseStatus {
+        if self.paused {
+            ParseStatus::Paused
+        } else if self.current_state == ParserState::Upgraded {
+            ParseStatus::Upgraded
+        } else if self.message_ended {
+            ParseStatus::Complete
+        } else {
+            ParseStatus::NeedMore
+        }
     }
 
     fn parse_http<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
     -> HttpParserResult<&'r [u8]> {
+        let base_offset = self.step_offset;
         let mut curr_input = input;
         loop {
-            let res = match self.current_state {
-                ParserState::FirstLine => unreachable!(),
-                ParserState::Headers => try!(self.parse_header(cb, curr_input)),
-                ParserState::HeaderEnd => try!(self.parse_header_end(cb, curr_input)),
-                ParserState::Body(body_type) => try!(self.parse_body(cb, curr_input, body_type)),
+            let state = self.current_state;
+            let offset = (input.len() - curr_input.len()) as u64;
+            self.step_offset = base_offset + offset;
+            let locate = |e: HttpParserError| e.shift(offset).in_phase(state.phase());
+            let res = match state {
+                ParserState::FirstLine | ParserState::Resync => unreachable!(),
+                ParserState::Headers => try!(self.parse_header(cb, curr_input).map_err(locate)),
+                ParserState::HeaderEnd => try!(self.parse_header_end(cb, curr_input).map_err(locate)),
+                ParserState::Body(body_type) => {
+                    try!(self.parse_body(cb, curr_input, body_type).map_err(locate))
+                },
+                ParserState::Trailers => try!(self.parse_header(cb, curr_input).map_err(locate)),
+                ParserState::TrailerEnd => try!(self.parse_trailer_end(cb, curr_input).map_err(locate)),
                 ParserState::Done => {
-                    cb.on_end(self);
-                    self.body_type = match self.parser_type {
-                        ParserType::Request => BodyType::NoBody,
-                        ParserType::Response => BodyType::EOF,
-                    };
-                    self.body_finished = false;
-                    self.current_state = ParserState::FirstLine;
-                    self.expect_body = ExpectBody::Maybe;
+                    try!(self.end_message(cb).map_err(locate));
                     return Ok(curr_input);
-                }
+                },
+                ParserState::Upgraded => return Ok(curr_input),
             };
 
             curr_input = res.0;
@@ -154,6 +626,9 @@ impl HttpParser {
             match res.1 {
                 BufferState::Ready(next_state) => {
                     self.current_state = next_state;
+                    if self.paused {
+                        return Ok(curr_input);
+                    }
                 },
                 BufferState::Incomplete => {
                     return Ok(curr_input);
@@ -162,13 +637,131 @@ impl HttpParser {
         }
     }
 
+    /// Handles an error from parsing the input, either by returning it or, when recovering from
+    /// errors, by reporting it and abandoning the current message. Returns the input from where
+    /// the error was, to look for the next message in.
+    fn recover<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8],
+                                               error: HttpParserError)
+    -> HttpParserResult<&'r [u8]> {
+        if !self.config.recover_from_errors {
+            return Err(error);
+        }
+        if let ErrorKind::Callback(_) = error.kind {
+            return Err(error);
+        }
+
+        let pos = cmp::min(error.offset.saturating_sub(self.bytes_consumed) as usize, input.len());
+        // The message that failed mustn't be found again, or we'd never get past it.
+        self.resync_at_line_start = error.offset > self.message_offset
+            && (pos == 0 || input[pos - 1] == b'\n');
+        self.bytes_consumed += pos as u64;
+        self.bytes_skipped = 0;
+        self.reset_message();
+        self.current_state = ParserState::Resync;
+        self.message_ended = true;
+        try!(cb.on_error(self, error));
+        Ok(&input[pos..])
+    }
+
+    /// Skips input until the next start line when resyncing after an error, returning the rest
+    /// of the input.
+    fn resync<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
+    -> HttpParserResult<&'r [u8]> {
+        if self.current_state != ParserState::Resync || self.paused {
+            return Ok(input);
+        }
+
+        let max_length = self.config.max_start_line_length.unwrap_or(resync::DEFAULT_MAX_LENGTH);
+        match resync::find_start_line(input, self.resync_at_line_start, self.parser_type, max_length) {
+            Ok(pos) => {
+                self.bytes_consumed += pos as u64;
+                let skipped = self.bytes_skipped + pos as u64;
+                self.bytes_skipped = 0;
+                self.current_state = ParserState::FirstLine;
+                try!(cb.on_resync(self, skipped));
+                Ok(&input[pos..])
+            },
+            Err(pos) => {
+                self.bytes_consumed += pos as u64;
+                self.bytes_skipped += pos as u64;
+                if pos < input.len() {
+                    self.resync_at_line_start = true;
+                } else if let Some(last) = input.last() {
+                    self.resync_at_line_start = *last == b'\n';
+                }
+                Ok(&input[pos..])
+            },
+        }
+    }
+
+    fn begin_message<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T) -> HttpParserResult<()> {
+        // Each call to parse_request/parse_response stops at the end of a message, so the next
+        // message always starts at the beginning of the input.
+        self.message_started = true;
+        self.message_offset = self.bytes_consumed;
+        self.messages_begun += 1;
+        try!(cb.on_message_begin(self));
+        Ok(())
+    }
+
+    fn end_message<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T) -> HttpParserResult<()> {
+        self.message_ended = true;
+        try!(cb.on_end(self));
+        if self.upgrade {
+            try!(cb.on_upgrade(self));
+            self.reset_message();
+            self.current_state = ParserState::Upgraded;
+        } else {
+            self.reset_message();
+        }
+        Ok(())
+    }
+
+    /// Resets the per message state, ready for the next message.
+    fn reset_message(&mut self) {
+        self.body_type = self.parser_type.default_body_type();
+        self.message_type = self.parser_type;
+        self.current_state = ParserState::FirstLine;
+        self.expect_body = ExpectBody::Maybe;
+        self.skip_body = false;
+        self.message_started = false;
+        self.header_scan_offset = 0;
+        self.header_count = 0;
+        self.headers_size = 0;
+        self.trailer_count = 0;
+        self.body_size = 0;
+        self.framing = Framing::default();
+        self.response_to = RequestKind::Other;
+        self.status_code = None;
+        self.upgrade = false;
+        self.connection = ConnectionOptions::default();
+        self.expect_continue = false;
+        self.announced_trailers.clear();
+    }
+
     fn parse_request_line<'r, T: HttpRequestCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
     -> HttpParserResult<ParserReturn<'r>> {
-        Ok(match nom_parsers::request_line(input) {
-            IResult::Error(_) => return Err(HttpParserError::BadFirstLine),
-            IResult::Incomplete(_) => ParserReturn(input, BufferState::Incomplete),
+        let parsed = if self.is_strict() {
+            nom_parsers::request_line_strict(input)
+        } else {
+            nom_parsers::request_line(input)
+        };
+        Ok(match parsed {
+            IResult::Error(_) => {
+                let (pos, reason) = diagnose::request_line(input, self.is_strict());
+                return Err(HttpParserError::at(input, pos, ErrorKind::BadFirstLine(reason)));
+            },
+            IResult::Incomplete(_) => {
+                try!(self.check_start_line_length(input.len()));
+                ParserReturn(input, BufferState::Incomplete)
+            },
             IResult::Done(i, request) => {
-                cb.on_request_line(self, request);
+                let line = &input[..input.len() - i.len()];
+                try!(self.check_start_line_length(line.len()));
+                try!(self.report_anomalies(cb, 0, anomalies::request_line(line)));
+                self.upgrade = request.method == Method::Connect;
+                self.version = request.version;
+                try!(cb.on_request_line(self, request));
                 ParserReturn(i, BufferState::Ready(ParserState::Headers))
             }
         })
@@ -176,82 +769,389 @@ impl HttpParser {
 
     fn parse_response_line<'r, T: HttpResponseCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
     -> HttpParserResult<ParserReturn<'r>> {
-        Ok(match nom_parsers::response_line(input) {
-            IResult::Error(_) => return Err(HttpParserError::BadFirstLine),
-            IResult::Incomplete(_) => ParserReturn(input, BufferState::Incomplete),
+        let parsed = if self.is_strict() {
+            nom_parsers::response_line_strict(input)
+        } else {
+            nom_parsers::response_line(input)
+        };
+        Ok(match parsed {
+            IResult::Error(_) => {
+                let (pos, reason) = diagnose::response_line(input, self.is_strict());
+                return Err(HttpParserError::at(input, pos, ErrorKind::BadFirstLine(reason)));
+            },
+            IResult::Incomplete(_) => {
+                try!(self.check_start_line_length(input.len()));
+                ParserReturn(input, BufferState::Incomplete)
+            },
             IResult::Done(i, response) => {
-                cb.on_response_line(self, response);
+                let line = &input[..input.len() - i.len()];
+                try!(self.check_start_line_length(line.len()));
+                try!(self.report_anomalies(cb, 0, anomalies::response_line(line)));
+                let code = response.code;
+                self.status_code = Some(code);
+                self.version = response.version;
+                if !code.is_informational() || code.as_u16() == 101 {
+                    self.response_to = self.request_kinds.pop_front().unwrap_or(RequestKind::Other);
+                }
+                self.upgrade = code.as_u16() == 101
+                    || (self.response_to == RequestKind::Connect && code.is_success());
+                try!(cb.on_response_line(self, response));
                 ParserReturn(i, BufferState::Ready(ParserState::Headers))
             }
         })
     }
 
+    fn check_start_line_length(&self, size: usize) -> HttpParserResult<()> {
+        if let Some(limit) = limit_exceeded(self.config.max_start_line_length, size) {
+            return Err(ErrorKind::StartLineTooLong{limit: limit, size: size}.into());
+        }
+        Ok(())
+    }
+
+    /// Parses header lines, or trailer lines if we're in the `Trailers` state.
     fn parse_header<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T, input: &'r[u8])
     -> HttpParserResult<ParserReturn<'r>> {
+        let trailers = self.current_state == ParserState::Trailers;
         let mut start = 0;
         loop {
-            match nom_parsers::header(&input[start..]) {
+            if !self.header_line_complete(&input[start..]) {
+                let size = input.len() - start;
+                try!(self.check_header_line_length(size).map_err(|e| e.near(input, start)));
+                if !trailers {
+                    try!(self.check_headers_size(self.headers_size + size).map_err(|e| e.near(input, start)));
+                }
+                return Ok(ParserReturn(&input[start..], BufferState::Incomplete));
+            }
+
+            let parsed = if self.is_strict() {
+                nom_parsers::header_strict(&input[start..])
+            } else {
+                nom_parsers::header(&input[start..])
+            };
+            match parsed {
                 IResult::Error(_) => {
-                    return Ok(ParserReturn(&input[start..], BufferState::Ready(ParserState::HeaderEnd)))
+                    let next_state = if trailers { ParserState::TrailerEnd } else { ParserState::HeaderEnd };
+                    return Ok(ParserReturn(&input[start..], BufferState::Ready(next_state)))
+                },
+                IResult::Incomplete(_) => {
+                    return Ok(ParserReturn(&input[start..], BufferState::Incomplete))
                 },
-                IResult::Incomplete(_) => return Ok(ParserReturn(input, BufferState::Incomplete)),
                 IResult::Done(i, (name, value)) => {
-                    cb.on_header(self, name, value);
-                    if let Some(body_type) = try!(body_type_from_header(name, value)) {
-                        self.body_type = body_type;
+                    let line = &input[start..input.len() - i.len()];
+                    let size = line.len();
+                    try!(self.check_header_line_length(size).map_err(|e| e.near(input, start)));
+                    try!(self.report_anomalies(cb, start, anomalies::header_line(line)).map_err(
+                        |e| HttpParserError::at(input, start, ErrorKind::Callback(e))
+                    ));
+                    if trailers {
+                        self.trailer_count += 1;
+                        if let Some(limit) = limit_exceeded(self.config.max_trailer_count, self.trailer_count) {
+                            let count = self.trailer_count;
+                            return Err(HttpParserError::at(
+                                input, start, ErrorKind::TooManyTrailers{limit: limit, count: count}
+                            ));
+                        }
+                        if self.config.require_announced_trailers
+                            && !self.announced_trailers.iter().any(|t| t.eq_ignore_ascii_case(name)) {
+                            return Err(HttpParserError::at(input, start, ErrorKind::UnannouncedTrailer));
+                        }
+
+                        try!(cb.on_trailer(self, name, value).map_err(
+                            |e| HttpParserError::at(input, start, ErrorKind::Callback(e))
+                        ));
+                    } else {
+                        self.header_count += 1;
+                        if let Some(limit) = limit_exceeded(self.config.max_header_count, self.header_count) {
+                            let count = self.header_count;
+                            return Err(HttpParserError::at(
+                                input, start, ErrorKind::TooManyHeaders{limit: limit, count: count}
+                            ));
+                        }
+                        self.headers_size += size;
+                        try!(self.check_headers_size(self.headers_size).map_err(|e| e.near(input, start)));
+
+                        let is_content_length = b"content-length".eq_ignore_ascii_case(name);
+                        let is_transfer_encoding = b"transfer-encoding".eq_ignore_ascii_case(name);
+                        let mut anomalies = Vec::new();
+                        if is_content_length
+                            && (self.framing.has_content_length()
+                                || headers::comma_separated(value).count() > 1) {
+                            anomalies.push((0, Anomaly::DuplicateContentLength));
+                        }
+                        if !self.framing.is_conflicting()
+                            && ((is_content_length && self.framing.has_transfer_encoding())
+                                || (is_transfer_encoding && self.framing.has_content_length())) {
+                            anomalies.push((0, Anomaly::TransferEncodingWithContentLength));
+                        }
+                        try!(self.report_anomalies(cb, start, anomalies).map_err(
+                            |e| HttpParserError::at(input, start, ErrorKind::Callback(e))
+                        ));
+
+                        try!(cb.on_header(self, name, value).map_err(
+                            |e| HttpParserError::at(input, start, ErrorKind::Callback(e))
+                        ));
+
+                        try!(self.framing.on_header(name, value).map_err(
+                            |e| HttpParserError::at(input, start, ErrorKind::BadHeaderValue(e))
+                        ));
+                        self.connection.on_header(name, value);
+                        if b"expect".eq_ignore_ascii_case(name) && headers::expects_continue(value) {
+                            self.expect_continue = self.is_request() && self.version >= (1, 1);
+                        }
+                        if b"trailer".eq_ignore_ascii_case(name) && self.config.require_announced_trailers {
+                            for field in headers::comma_separated(value) {
+                                self.announced_trailers.push(field.to_owned());
+                            }
+                        }
                     }
 
                     start = input.len() - i.len();
+
+                    if self.paused {
+                        let state = self.current_state;
+                        return Ok(ParserReturn(i, BufferState::Ready(state)));
+                    }
                 }
             }
         }
     }
 
+    fn check_header_line_length(&self, size: usize) -> HttpParserResult<()> {
+        if let Some(limit) = limit_exceeded(self.config.max_header_line_length, size) {
+            return Err(ErrorKind::HeaderLineTooLong{limit: limit, size: size}.into());
+        }
+        Ok(())
+    }
+
+    fn check_headers_size(&self, size: usize) -> HttpParserResult<()> {
+        if let Some(limit) = limit_exceeded(self.config.max_headers_size, size) {
+            return Err(ErrorKind::HeadersTooLarge{limit: limit, size: size}.into());
+        }
+        Ok(())
+    }
+
+    /// Checks whether the input starts with a complete header line (or the empty line that ends
+    /// the headers), and so is worth handing to the header parser.
+    ///
+    /// Remembers how much of the line it has already scanned, so that when the line arrives a
+    /// few bytes at a time we only look at each byte once.
+    fn header_line_complete(&mut self, input: &[u8]) -> bool {
+        let mut idx = cmp::min(self.header_scan_offset, input.len());
+        while idx < input.len() {
+            if input[idx] == b'\n' {
+                if idx == 0 || (idx == 1 && input[0] == b'\r') {
+                    self.header_scan_offset = 0;
+                    return true;
+                }
+
+                // We need to see the start of the next line to know whether this is obs-fold.
+                match input.get(idx + 1) {
+                    Some(&b' ') | Some(&b'\t') => {},
+                    Some(_) => {
+                        self.header_scan_offset = 0;
+                        return true;
+                    },
+                    None => {
+                        self.header_scan_offset = idx;
+                        return false;
+                    },
+                }
+            }
+            idx += 1;
+        }
+        self.header_scan_offset = idx;
+        false
+    }
+
     fn parse_header_end<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
     -> HttpParserResult<ParserReturn<'r>> {
-        Ok(match nom_parsers::empty_line(input) {
-            IResult::Error(_) => return Err(HttpParserError::BadHeader),
+        Ok(match self.empty_line(input) {
+            IResult::Error(_) => {
+                let (pos, reason) = diagnose::header_line(input, self.is_strict());
+                return Err(HttpParserError::at(input, pos, ErrorKind::BadHeader(reason)));
+            },
             IResult::Incomplete(_) => ParserReturn(input, BufferState::Incomplete),
             IResult::Done(i, _) => {
-                if self.body_finished {
-                    // We were dealing with trailing headers, so now we've finished.
-                    ParserReturn(i, BufferState::Ready(ParserState::Done))
+                try!(self.report_anomalies(cb, 0, anomalies::line(&input[..input.len() - i.len()])));
+
+                let is_request = self.is_request();
+                let framing_body_type = if self.response_has_no_body() {
+                    Some(BodyType::NoBody)
                 } else {
-                    let body_type = self.body_type;
-                    self.expect_body = cb.on_headers_finished(self, body_type);
-
-                    let body_state = match self.expect_body {
-                        ExpectBody::Maybe => match self.body_type {
-                            BodyType::Chunked => BodyTypeState::Chunked(ChunkedState::Header),
-                            BodyType::Length(len) => BodyTypeState::Lenth(len),
-                            BodyType::EOF => BodyTypeState::EOF,
-                            BodyType::NoBody => BodyTypeState::NoBody,
-                        },
-                        ExpectBody::No => BodyTypeState::NoBody,
-                    };
-
-                    ParserReturn(i, BufferState::Ready(ParserState::Body(body_state)))
+                    try!(self.framing.body_type(
+                        is_request, self.config.allow_conflicting_framing
+                    ))
+                };
+                if let Some(body_type) = framing_body_type {
+                    self.body_type = body_type;
+                }
+
+                if self.is_request() && self.connection.upgrade_requested(self.version) {
+                    self.upgrade = true;
+                }
+                // RFC 9112 section 6.3 requires closing the connection after a message with
+                // both Transfer-Encoding and Content-Length, as it may be an attempt at smuggling.
+                self.keep_alive = self.connection.keep_alive(self.version)
+                    && self.body_type != BodyType::EOF
+                    && !self.framing.is_conflicting()
+                    && !self.upgrade;
+
+                let body_type = self.body_type;
+                self.expect_body = try!(cb.on_headers_finished(self, body_type));
+
+                // If a client that was waiting for a 100 Continue gets a final response
+                // instead it may or may not send the body, so we can't tell where the next
+                // message starts.
+                let has_body = match self.body_type {
+                    BodyType::Chunked | BodyType::EOF => true,
+                    BodyType::Length(len) => len > 0,
+                    BodyType::NoBody => false,
+                };
+                if self.expect_continue && self.expect_body == ExpectBody::No && has_body {
+                    self.keep_alive = false;
+                }
+
+                let body_state = match self.expect_body {
+                    ExpectBody::Maybe => match self.body_type {
+                        BodyType::Chunked => BodyTypeState::Chunked(ChunkedState::Header),
+                        BodyType::Length(len) => BodyTypeState::Lenth(len),
+                        BodyType::EOF => BodyTypeState::EOF,
+                        BodyType::NoBody => BodyTypeState::NoBody,
+                    },
+                    ExpectBody::No => BodyTypeState::NoBody,
+                };
+
+                if let BodyTypeState::Lenth(len) = body_state {
+                    try!(self.add_body_size(len));
                 }
+
+                ParserReturn(i, BufferState::Ready(ParserState::Body(body_state)))
             }
         })
     }
 
+    fn parse_trailer_end<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8])
+    -> HttpParserResult<ParserReturn<'r>> {
+        Ok(match self.empty_line(input) {
+            IResult::Error(_) => {
+                let (pos, reason) = diagnose::header_line(input, self.is_strict());
+                return Err(HttpParserError::at(input, pos, ErrorKind::BadHeader(reason)));
+            },
+            IResult::Incomplete(_) => ParserReturn(input, BufferState::Incomplete),
+            IResult::Done(i, _) => {
+                try!(self.report_anomalies(cb, 0, anomalies::line(&input[..input.len() - i.len()])));
+                try!(cb.on_trailers_finished(self));
+                ParserReturn(i, BufferState::Ready(ParserState::Done))
+            }
+        })
+    }
+
+    /// Whether the current message is a response that never has a body, whatever its headers
+    /// say, as per RFC 9112 section 6.3.
+    fn response_has_no_body(&self) -> bool {
+        match self.message_type {
+            ParserType::Request | ParserType::Both => false,
+            ParserType::Response => {
+                self.response_to == RequestKind::Head
+                || (self.response_to == RequestKind::Connect && self.is_success())
+                || self.status_code.map_or(false, |code| {
+                    code.is_informational() || code.as_u16() == 204 || code.as_u16() == 304
+                })
+            },
+        }
+    }
+
+    /// Reports the anomalies found in a line that starts at `line_start` in the input of the
+    /// current parsing step.
+    fn report_anomalies<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T, line_start: usize,
+                                                        anomalies: Vec<(usize, Anomaly)>)
+    -> CallbackResult<()> {
+        for (pos, kind) in anomalies {
+            let offset = self.step_offset + (line_start + pos) as u64;
+            try!(cb.on_anomaly(self, kind, offset));
+        }
+        Ok(())
+    }
+
+    fn is_strict(&self) -> bool {
+        self.config.strictness == Strictness::Strict
+    }
+
+    fn empty_line<'r>(&self, input: &'r [u8]) -> IResult<'r, &'r [u8], &'r [u8]> {
+        if self.is_strict() {
+            nom_parsers::empty_line_strict(input)
+        } else {
+            nom_parsers::empty_line(input)
+        }
+    }
+
+    fn is_request(&self) -> bool {
+        self.message_type == ParserType::Request
+    }
+
+    fn set_message_type(&mut self, message_type: ParserType) {
+        self.message_type = message_type;
+        self.body_type = message_type.default_body_type();
+    }
+
+    fn is_success(&self) -> bool {
+        self.status_code.map_or(false, |code| code.is_success())
+    }
+
+    /// Adds to the running total of the body size, checking it against the configured limit.
+    fn add_body_size(&mut self, size: u64) -> HttpParserResult<()> {
+        self.body_size = self.body_size.saturating_add(size);
+        if let Some(limit) = limit_exceeded(self.config.max_body_size, self.body_size) {
+            return Err(ErrorKind::BodyTooLarge{limit: limit, size: self.body_size}.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the length of the size and extensions of a chunk header line, which may not be
+    /// complete yet.
+    fn check_chunk_header_length(&self, line: &[u8]) -> HttpParserResult<()> {
+        let size = chunk_size_length(line);
+        if size > MAX_CHUNK_SIZE_LENGTH {
+            return Err(HttpParserError::at(
+                line, 0, ErrorKind::ChunkSizeTooLong{limit: MAX_CHUNK_SIZE_LENGTH, size: size}
+            ));
+        }
+        if let Some(max) = self.config.max_chunk_extensions_length {
+            let size = chunk_extensions_length(line);
+            if size > max {
+                return Err(HttpParserError::at(
+                    line, 0, ErrorKind::ChunkExtensionsTooLong{limit: max, size: size}
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_body_data<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T, data: &'r [u8])
+    -> HttpParserResult<()> {
+        if !self.skip_body {
+            try!(cb.on_chunk(self, data));
+        }
+        Ok(())
+    }
+
     fn parse_body<'r, T: HttpMessageCallbacks<'r>>(&mut self, cb: &mut T, input: &'r [u8], body_type: BodyTypeState)
     -> HttpParserResult<ParserReturn<'r>> {
         Ok(match body_type {
             BodyTypeState::Lenth(size) => {
                 if input.len() == 0 && size != 0 {
                     return Ok(ParserReturn(input, BufferState::Incomplete));
-                } else if input.len() < size {
-                    cb.on_chunk(self, input);
+                } else if (input.len() as u64) < size {
+                    try!(self.on_body_data(cb, input));
                     ParserReturn(
                         b"",
                         BufferState::Ready(
-                            ParserState::Body(BodyTypeState::Lenth(size - input.len()))
+                            ParserState::Body(BodyTypeState::Lenth(size - input.len() as u64))
                         )
                     )
                 } else {
-                    cb.on_chunk(self, &input[..size]);
+                    let size = size as usize;
+                    try!(self.on_body_data(cb, &input[..size]));
                     ParserReturn(&input[size..], BufferState::Ready(ParserState::Done))
                 }
             },
@@ -262,19 +1162,35 @@ impl HttpParser {
 
                 match chunk_state {
                     ChunkedState::Header => {
-                        match nom_parsers::chunk_parser(input) {
-                            IResult::Error(_) => return Err(HttpParserError::BadBodyChunkHeader),
-                            IResult::Incomplete(_) => ParserReturn(input, BufferState::Incomplete),
+                        let parsed = if self.is_strict() {
+                            nom_parsers::chunk_parser_strict(input)
+                        } else {
+                            nom_parsers::chunk_parser(input)
+                        };
+                        match parsed {
+                            IResult::Error(_) => {
+                                let (pos, reason) = diagnose::chunk_header(input, self.is_strict());
+                                return Err(HttpParserError::at(
+                                    input, pos, ErrorKind::BadBodyChunkHeader(reason)
+                                ));
+                            },
+                            IResult::Incomplete(_) => {
+                                try!(self.check_chunk_header_length(input));
+                                ParserReturn(input, BufferState::Incomplete)
+                            },
                             IResult::Done(i, chunk_header) => {
-                                // TODO: Handle chunk_header
+                                let line = &input[..input.len() - i.len()];
+                                try!(self.check_chunk_header_length(line));
+                                try!(self.report_anomalies(cb, 0, anomalies::line(line)));
+                                let size = chunk_header.size;
+                                try!(self.add_body_size(size).map_err(|e| e.near(input, 0)));
+                                try!(cb.on_chunk_header(self, chunk_header));
                                 ParserReturn(
                                     i,
                                     BufferState::Ready(
                                         ParserState::Body(
                                             BodyTypeState::Chunked(
-                                                ChunkedState::Data(
-                                                    chunk_header.size
-                                                )
+                                                ChunkedState::Data(size)
                                             )
                                         )
                                     )
@@ -283,23 +1199,24 @@ impl HttpParser {
                         }
                     },
                     ChunkedState::Data(size) => {
-                        if input.len() < size {
-                            cb.on_chunk(self, input);
+                        if (input.len() as u64) < size {
+                            try!(self.on_body_data(cb, input));
                             ParserReturn(
                                 b"",
                                 BufferState::Ready(
                                     ParserState::Body(
                                         BodyTypeState::Chunked(
                                             ChunkedState::Data(
-                                                size - input.len()
+                                                size - input.len() as u64
                                             )
                                         )
                                     )
                                 )
                             )
                         } else {
+                            let size = size as usize;
                             if size > 0 {
-                                cb.on_chunk(self, &input[..size]);
+                                try!(self.on_body_data(cb, &input[..size]));
                                 ParserReturn(
                                     &input[size..],
                                     BufferState::Ready(
@@ -309,21 +1226,27 @@ impl HttpParser {
                                     )
                                 )
                             } else {
-                                self.body_finished = true;
                                 ParserReturn(
                                     &input[size..],
                                     BufferState::Ready(
-                                        ParserState::Headers
+                                        ParserState::Trailers
                                     )
                                 )
                             }
                         }
                     },
                     ChunkedState::DataEnd => {
-                        match nom_parsers::empty_line(input) {
-                            IResult::Error(_) => return Err(HttpParserError::BadBodyChunkHeader),
+                        match self.empty_line(input) {
+                            IResult::Error(_) => {
+                                let (pos, reason) = diagnose::chunk_data_end(input, self.is_strict());
+                                return Err(HttpParserError::at(
+                                    input, pos, ErrorKind::BadBodyChunkHeader(reason)
+                                ));
+                            },
                             IResult::Incomplete(_) => ParserReturn(input, BufferState::Incomplete),
                             IResult::Done(i, _) => {
+                                let line = &input[..input.len() - i.len()];
+                                try!(self.report_anomalies(cb, 0, anomalies::line(line)));
                                 ParserReturn(
                                     i,
                                     BufferState::Ready(
@@ -342,7 +1265,8 @@ impl HttpParser {
                     return Ok(ParserReturn(input, BufferState::Incomplete));
                 }
 
-                cb.on_chunk(self, input);
+                try!(self.add_body_size(input.len() as u64));
+                try!(self.on_body_data(cb, input));
                 ParserReturn(b"", BufferState::Incomplete)
             },
             BodyTypeState::NoBody => ParserReturn(input, BufferState::Ready(ParserState::Done)),
@@ -351,17 +1275,28 @@ impl HttpParser {
 }
 
 
-fn body_type_from_header(name: &[u8], value: &[u8]) -> Result<Option<BodyType>, HttpHeaderParseError> {
-    if b"transfer-encoding".eq_ignore_ascii_case(name) {
-        // TODO: Comparison isn't this simple.
-        if b"chunked".eq_ignore_ascii_case(value) {
-            return Ok(Some(BodyType::Chunked))
-        }
-    } else if b"content-length".eq_ignore_ascii_case(name) {
-        return match integer_decoder::dec_buf_to_int(value) {
-            Ok(size) => Ok(Some(BodyType::Length(size))),
-            Err(e) => Err(HttpHeaderParseError::ContentLength(e)),
-        };
+/// Returns the limit if the size exceeds it.
+fn limit_exceeded<N: PartialOrd + Copy>(limit: Option<N>, size: N) -> Option<N> {
+    match limit {
+        Some(limit) if size > limit => Some(limit),
+        _ => None,
+    }
+}
+
+/// The length of the chunk extensions in a (possibly partial) chunk size line, i.e. everything
+/// after the chunk size excluding the line ending.
+fn chunk_size_length(line: &[u8]) -> usize {
+    line.iter().take_while(|c| (**c as char).is_digit(16)).count()
+}
+
+fn chunk_extensions_length(line: &[u8]) -> usize {
+    let size_len = chunk_size_length(line);
+    let mut end = line.len();
+    if end > size_len && line[end - 1] == b'\n' {
+        end -= 1;
+    }
+    if end > size_len && line[end - 1] == b'\r' {
+        end -= 1;
     }
-    Ok(None)
+    end - size_len
 }
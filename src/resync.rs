@@ -0,0 +1,132 @@
+//! Finds where to carry on parsing after an error, when the parser is set to recover from
+//! errors.
+//!
+//! A line is taken to be a start line if it looks like one for long enough: a method token
+//! followed by a request-target and `HTTP/x.y`, or `HTTP/x.y` followed by a status code.
+
+use nom_parsers::{is_space, is_token_char};
+use parser::ParserType;
+
+
+/// How long a start line may be when looking for one, if `max_start_line_length` isn't set.
+pub const DEFAULT_MAX_LENGTH: usize = 8 * 1024;
+
+/// Why a position isn't the start of a message, or isn't known to be one yet.
+enum Mismatch { Invalid, Incomplete }
+
+fn is_target_char(chr: u8) -> bool {
+    chr > b' ' && chr < 0x7f
+}
+
+/// Skips at least one byte that matches `f`, returning the position after them.
+fn skip<F: Fn(u8) -> bool>(input: &[u8], pos: usize, f: F) -> Result<usize, Mismatch> {
+    let end = input[pos..].iter().position(|c| !f(*c)).map_or(input.len(), |p| pos + p);
+    if end == input.len() {
+        Err(Mismatch::Incomplete)
+    } else if end == pos {
+        Err(Mismatch::Invalid)
+    } else {
+        Ok(end)
+    }
+}
+
+/// Matches `HTTP/x.y` at `pos`, returning the position after it.
+fn version(input: &[u8], pos: usize) -> Result<usize, Mismatch> {
+    for (idx, expected) in b"HTTP/0.0".iter().enumerate() {
+        let chr = match input.get(pos + idx) {
+            Some(chr) => *chr,
+            None => return Err(Mismatch::Incomplete),
+        };
+        let matches = if *expected == b'0' { chr.is_ascii_digit() } else { chr == *expected };
+        if !matches {
+            return Err(Mismatch::Invalid);
+        }
+    }
+    Ok(pos + 8)
+}
+
+fn request_line(input: &[u8]) -> Result<usize, Mismatch> {
+    let method_end = try!(skip(input, 0, is_token_char));
+    let target_start = try!(skip(input, method_end, is_space));
+    let target_end = try!(skip(input, target_start, is_target_char));
+    let version_start = try!(skip(input, target_end, is_space));
+    version(input, version_start)
+}
+
+fn response_line(input: &[u8]) -> Result<usize, Mismatch> {
+    let version_end = try!(version(input, 0));
+    let code_start = try!(skip(input, version_end, is_space));
+    for idx in code_start..code_start + 3 {
+        match input.get(idx) {
+            Some(chr) if chr.is_ascii_digit() => {},
+            Some(_) => return Err(Mismatch::Invalid),
+            None => return Err(Mismatch::Incomplete),
+        }
+    }
+    Ok(code_start + 3)
+}
+
+fn start_line(input: &[u8], parser_type: ParserType) -> Result<usize, Mismatch> {
+    match parser_type {
+        ParserType::Request => request_line(input),
+        ParserType::Response => response_line(input),
+        ParserType::Both => match (request_line(input), response_line(input)) {
+            (Ok(end), _) | (_, Ok(end)) => Ok(end),
+            (Err(Mismatch::Incomplete), _) | (_, Err(Mismatch::Incomplete)) => {
+                Err(Mismatch::Incomplete)
+            },
+            _ => Err(Mismatch::Invalid),
+        },
+    }
+}
+
+/// Looks for the first line in the input that starts a message of the given type.
+/// `at_line_start` says whether the input starts at the beginning of a line.
+///
+/// Returns the position of the start line if there is one, or otherwise the position to carry
+/// on looking from once there's more input. A line that could still be a start line is kept
+/// until it's longer than `max_length`, and is then skipped like any other.
+pub fn find_start_line(input: &[u8], at_line_start: bool, parser_type: ParserType,
+                       max_length: usize)
+-> Result<usize, usize> {
+    let mut line_start = if at_line_start { Some(0) } else { next_line(input, 0) };
+    while let Some(start) = line_start {
+        match start_line(&input[start..], parser_type) {
+            Ok(_) => return Ok(start),
+            Err(Mismatch::Incomplete) if input.len() - start <= max_length => return Err(start),
+            Err(_) => line_start = next_line(input, start),
+        }
+    }
+    Err(input.len())
+}
+
+fn next_line(input: &[u8], pos: usize) -> Option<usize> {
+    input[pos..].iter().position(|c| *c == b'\n').map(|p| pos + p + 1)
+}
+
+
+#[test]
+fn test_find_start_line() {
+    let cases = vec![
+        (&b"GET / HTTP/1.1\r\n"[..], true, ParserType::Request, Ok(0)),
+        (&b"GET / HTTP/1.1\r\n"[..], false, ParserType::Request, Err(16)),
+        (&b"junk\r\nmore junk\r\nPOST /x HTTP/1.0\r\n"[..], false, ParserType::Request, Ok(17)),
+        (&b"junk\r\nHTTP/1.1 200 OK\r\n"[..], false, ParserType::Request, Err(23)),
+        (&b"junk\r\nHTTP/1.1 200 OK\r\n"[..], false, ParserType::Response, Ok(6)),
+        (&b"junk\r\nHTTP/1.1 200 OK\r\n"[..], false, ParserType::Both, Ok(6)),
+        (&b"junk\r\nGET /x HTT"[..], false, ParserType::Request, Err(6)),
+        (&b"junk\r\nGET /x HTTP/1.1"[..], false, ParserType::Both, Ok(6)),
+        (&b"junk\r\nHTTP/1.1 20"[..], false, ParserType::Both, Err(6)),
+        (&b"junk\r\n\r\nGET\r\n"[..], false, ParserType::Request, Err(13)),
+        (&b"junk\r\nAAAAAAAAAAAAAAAA"[..], false, ParserType::Request, Err(6)),
+        (&b"junk\r\nAAAAAAAAAAAAAAAAA"[..], false, ParserType::Request, Err(23)),
+        (&b"junk\r\nGET /xxxxxxxxxxxxx"[..], false, ParserType::Request, Err(24)),
+    ];
+
+    for (input, at_line_start, parser_type, expected) in cases {
+        assert_eq!(
+            expected, find_start_line(input, at_line_start, parser_type, 16),
+            "{:?}", String::from_utf8_lossy(input)
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::fmt;
+
+
+/// A response status code, as per RFC 9110 section 15.
+///
+/// Any three digit code from 100 to 999 is valid. Codes outside the classes defined by the RFC
+/// are allowed, but aren't in any of the classes below.
+#[derive(PartialEq,Eq,PartialOrd,Ord,Hash,Debug,Clone,Copy)]
+pub struct StatusCode(u16);
+
+impl StatusCode {
+    /// Returns None if the code is outside 100 to 999.
+    pub fn from_u16(code: u16) -> Option<StatusCode> {
+        if code >= 100 && code <= 999 {
+            Some(StatusCode(code))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// 1xx, the request was received and is being processed.
+    pub fn is_informational(&self) -> bool {
+        self.0 >= 100 && self.0 < 200
+    }
+
+    /// 2xx, the request was successfully received, understood and accepted.
+    pub fn is_success(&self) -> bool {
+        self.0 >= 200 && self.0 < 300
+    }
+
+    /// 3xx, further action needs to be taken to complete the request.
+    pub fn is_redirect(&self) -> bool {
+        self.0 >= 300 && self.0 < 400
+    }
+
+    /// 4xx, the request has bad syntax or can't be fulfilled.
+    pub fn is_client_error(&self) -> bool {
+        self.0 >= 400 && self.0 < 500
+    }
+
+    /// 5xx, the server failed to fulfil an apparently valid request.
+    pub fn is_server_error(&self) -> bool {
+        self.0 >= 500 && self.0 < 600
+    }
+
+    /// The reason phrase registered for the code with IANA, for writing responses. Returns None
+    /// for unregistered codes.
+    ///
+    /// Recipients should ignore the reason phrase of a response, as it's only informational
+    /// and often differs from these.
+    pub fn canonical_reason(&self) -> Option<&'static str> {
+        Some(match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            102 => "Processing",
+            103 => "Early Hints",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            203 => "Non-Authoritative Information",
+            204 => "No Content",
+            205 => "Reset Content",
+            206 => "Partial Content",
+            207 => "Multi-Status",
+            208 => "Already Reported",
+            226 => "IM Used",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            305 => "Use Proxy",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            402 => "Payment Required",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            407 => "Proxy Authentication Required",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            421 => "Misdirected Request",
+            422 => "Unprocessable Content",
+            423 => "Locked",
+            424 => "Failed Dependency",
+            425 => "Too Early",
+            426 => "Upgrade Required",
+            428 => "Precondition Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            451 => "Unavailable For Legal Reasons",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            506 => "Variant Also Negotiates",
+            507 => "Insufficient Storage",
+            508 => "Loop Detected",
+            510 => "Not Extended",
+            511 => "Network Authentication Required",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+
+#[test]
+fn test_status_code() {
+    assert_eq!(None, StatusCode::from_u16(0));
+    assert_eq!(None, StatusCode::from_u16(99));
+    assert_eq!(None, StatusCode::from_u16(1000));
+    assert_eq!(Some(999), StatusCode::from_u16(999).map(|c| c.as_u16()));
+
+    let cases = [
+        (100, [true, false, false, false, false]),
+        (204, [false, true, false, false, false]),
+        (308, [false, false, true, false, false]),
+        (451, [false, false, false, true, false]),
+        (599, [false, false, false, false, true]),
+        (600, [false, false, false, false, false]),
+    ];
+    for &(code, classes) in cases.iter() {
+        let code = StatusCode::from_u16(code).unwrap();
+        assert_eq!(classes, [
+            code.is_informational(), code.is_success(), code.is_redirect(),
+            code.is_client_error(), code.is_server_error(),
+        ], "{}", code);
+    }
+
+    assert_eq!(Some("Not Found"), StatusCode::from_u16(404).unwrap().canonical_reason());
+    assert_eq!(None, StatusCode::from_u16(599).unwrap().canonical_reason());
+}
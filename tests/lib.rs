@@ -1,6 +1,7 @@
 extern crate nom_http;
 
 use nom_http::*;
+use nom_http::errors::*;
 use std::collections::HashMap;
 use std::str;
 
@@ -53,7 +54,7 @@ macro_rules! create_request_test {
                             curr_input = http_parser.parse_request(
                                 &mut cb,
                                 curr_input,
-                            ).unwrap();
+                            ).unwrap().0;
                         }
                         assert_eq!($expected, cb);
                     }
@@ -94,7 +95,7 @@ macro_rules! create_response_test {
                             curr_input = http_parser.parse_response(
                                 &mut cb,
                                 curr_input,
-                            ).unwrap();
+                            ).unwrap().0;
                         }
                         assert_eq!($expected, cb);
                     }
@@ -262,6 +263,1447 @@ fn test_consumer() {
 }
 
 
+#[test]
+fn test_chunk_header_callback() {
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    http_parser.parse_request(
+        &mut cb,
+        br#"GET /test_url/ HTTP/1.1
+Transfer-Encoding: chunked
+
+5;foo=bar;no_val_param;q="Quoted \"Param\""
+Hello
+0;last
+
+"#
+    ).unwrap();
+
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "request_line GET /test_url/".to_owned(),
+            "header Transfer-Encoding: chunked".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk_header 5 foo=bar no_val_param q=Quoted \"Param\"".to_owned(),
+            "chunk Hello".to_owned(),
+            "chunk_header 0 last".to_owned(),
+            "trailers_finished".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+}
+
+
+#[test]
+fn test_pause_and_resume() {
+    let input = b"GET /test_url/ HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello";
+
+    let mut cb = EventRecorder::new();
+    cb.pause_on = Some("headers_finished");
+    let mut http_parser = HttpParser::new(ParserType::Request);
+
+    let (remaining, status) = http_parser.parse_request(&mut cb, input).unwrap();
+    assert_eq!(ParseStatus::Paused, status);
+    assert!(http_parser.is_paused());
+    assert_eq!(b"Hello", remaining);
+    assert_eq!(Some(&"headers_finished".to_owned()), cb.events.last());
+
+    // Nothing happens while we're paused.
+    let (remaining, status) = http_parser.parse_request(&mut cb, remaining).unwrap();
+    assert_eq!(ParseStatus::Paused, status);
+    assert_eq!(b"Hello", remaining);
+    assert_eq!(4, cb.events.len());
+
+    cb.pause_on = None;
+    http_parser.resume();
+    let (remaining, status) = http_parser.parse_request(&mut cb, remaining).unwrap();
+    assert_eq!(ParseStatus::Complete, status);
+    assert_eq!(b"", remaining);
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "request_line GET /test_url/".to_owned(),
+            "header Content-Length: 5".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk Hello".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+}
+
+#[test]
+fn test_pause_in_headers() {
+    let input = b"GET / HTTP/1.1\r\nFirst: 1\r\nSecond: 2\r\n\r\n";
+
+    let mut cb = EventRecorder::new();
+    cb.pause_on = Some("header First");
+    let mut http_parser = HttpParser::new(ParserType::Request);
+
+    let (remaining, status) = http_parser.parse_request(&mut cb, input).unwrap();
+    assert_eq!(ParseStatus::Paused, status);
+    assert_eq!(b"Second: 2\r\n\r\n", remaining);
+
+    cb.pause_on = None;
+    http_parser.resume();
+    assert_eq!((&b""[..], ParseStatus::Complete), http_parser.parse_request(&mut cb, remaining).unwrap());
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "request_line GET /".to_owned(),
+            "header First: 1".to_owned(),
+            "header Second: 2".to_owned(),
+            "headers_finished".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+}
+
+
+#[test]
+fn test_skip_body() {
+    let input = b"POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\
+X-Reject: yes\r\n\
+\r\n\
+5\r\nHello\r\n\
+0\r\n\
+\r\n\
+POST /second HTTP/1.1\r\n\
+Content-Length: 5\r\n\
+\r\n\
+World";
+
+    let mut cb = EventRecorder::new();
+    cb.skip_body_on = Some("header X-Reject");
+    let mut http_parser = HttpParser::new(ParserType::Request);
+
+    let remaining = http_parser.parse_request(&mut cb, input).unwrap().0;
+    let remaining = http_parser.parse_request(&mut cb, remaining).unwrap().0;
+    assert_eq!(b"", remaining);
+
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "request_line POST /upload".to_owned(),
+            "header Transfer-Encoding: chunked".to_owned(),
+            "header X-Reject: yes".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk_header 5".to_owned(),
+            "chunk_header 0".to_owned(),
+            "trailers_finished".to_owned(),
+            "end".to_owned(),
+            "message_begin 1 at 83".to_owned(),
+            "request_line POST /second".to_owned(),
+            "header Content-Length: 5".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk World".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+}
+
+
+#[test]
+fn test_finish_eof_body() {
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.parse_response(&mut cb, b"HTTP/1.0 200 OK\r\n\r\nHello").unwrap();
+    let (_, status) = http_parser.parse_response(&mut cb, b" World").unwrap();
+    assert_eq!(ParseStatus::NeedMore, status);
+    assert_eq!(Some(&"chunk  World".to_owned()), cb.events.last());
+
+    http_parser.finish(&mut cb).unwrap();
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "response_line 200".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk Hello".to_owned(),
+            "chunk  World".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+
+    // A clean close between messages isn't an error.
+    http_parser.finish(&mut cb).unwrap();
+}
+
+#[test]
+fn test_finish_truncated() {
+    let truncated = [
+        &b"GET /test_url/ HTT"[..],
+        &b"GET /test_url/ HTTP/1.1\r\nHost: exa"[..],
+        &b"GET /test_url/ HTTP/1.1\r\nContent-Length: 10\r\n\r\nHello"[..],
+        &b"GET /test_url/ HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHel"[..],
+        &b"GET /test_url/ HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n"[..],
+    ];
+
+    for input in truncated.iter() {
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::new(ParserType::Request);
+        http_parser.parse_request(&mut cb, input).unwrap();
+        match http_parser.finish(&mut cb) {
+            Err(HttpParserError { kind: ErrorKind::UnexpectedEof, .. }) => {},
+            r => panic!("Expected UnexpectedEof for {:?}: {:?}", String::from_utf8_lossy(input), r),
+        }
+        assert!(!cb.events.contains(&"end".to_owned()));
+    }
+}
+
+
+#[test]
+fn test_headers_arriving_byte_by_byte() {
+    let input = b"GET /test_url/ HTTP/1.1\r\n\
+Host: example.com\r\n\
+X-Folded: first\r\n second\r\n\
+Content-Length: 5\r\n\
+\r\n\
+Hello";
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+
+    // Simulate the data arriving one byte at a time, with the caller passing back whatever
+    // the parser didn't consume along with each new byte.
+    let mut start = 0;
+    for end in 1..input.len() + 1 {
+        let remaining = http_parser.parse_request(&mut cb, &input[start..end]).unwrap().0;
+        start = end - remaining.len();
+    }
+
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "request_line GET /test_url/".to_owned(),
+            "header Host: example.com".to_owned(),
+            "header X-Folded: first\r\n second".to_owned(),
+            "header Content-Length: 5".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk H".to_owned(),
+            "chunk e".to_owned(),
+            "chunk l".to_owned(),
+            "chunk l".to_owned(),
+            "chunk o".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+}
+
+
+#[test]
+fn test_large_body_sizes() {
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    http_parser.parse_request(
+        &mut cb, b"PUT /upload HTTP/1.1\r\nContent-Length: 5000000000\r\n\r\nHello"
+    ).unwrap();
+    assert_eq!(BodyType::Length(5000000000), http_parser.body_type);
+    assert_eq!(Some(&"chunk Hello".to_owned()), cb.events.last());
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    http_parser.parse_request(
+        &mut cb,
+        b"PUT /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n12A05F200\r\nHello"
+    ).unwrap();
+    assert_eq!(
+        vec!["chunk_header 5000000000".to_owned(), "chunk Hello".to_owned()],
+        &cb.events[4..]
+    );
+}
+
+#[test]
+fn test_content_length_overflow() {
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let res = http_parser.parse_request(
+        &mut cb, b"PUT /upload HTTP/1.1\r\nContent-Length: 18446744073709551616\r\n\r\n"
+    );
+    match res {
+        Err(HttpParserError { kind: ErrorKind::BadHeaderValue(
+            HttpHeaderParseError::ContentLength(IntegerDecodeError::Overflow)
+        ), .. }) => {},
+        r => panic!("Expected overflow error: {:?}", r),
+    }
+}
+
+
+#[test]
+fn test_limits() {
+    let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+    let cases = vec![
+        (
+            ParserConfig::new().max_start_line_length(16),
+            "GET /a/very/long/path HTTP/1.1\r\n\r\n".to_owned(),
+            "StartLineTooLong { limit: 16, size: 32 }",
+        ),
+        (
+            ParserConfig::new().max_start_line_length(16),
+            "GET /a/very/long/path".to_owned(),
+            "StartLineTooLong { limit: 16, size: 21 }",
+        ),
+        (
+            ParserConfig::new().max_header_line_length(20),
+            "GET / HTTP/1.1\r\nX-Long-Header: abcdefghij\r\n\r\n".to_owned(),
+            "HeaderLineTooLong { limit: 20, size: 27 }",
+        ),
+        (
+            ParserConfig::new().max_header_line_length(20),
+            "GET / HTTP/1.1\r\nX-Long-Header: abcdefghij".to_owned(),
+            "HeaderLineTooLong { limit: 20, size: 25 }",
+        ),
+        (
+            ParserConfig::new().max_header_count(2),
+            "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n".to_owned(),
+            "TooManyHeaders { limit: 2, count: 3 }",
+        ),
+        (
+            ParserConfig::new().max_headers_size(30),
+            "GET / HTTP/1.1\r\nA: 1234567890\r\nB: 1234567890\r\nC: 1234567890\r\n\r\n".to_owned(),
+            "HeadersTooLarge { limit: 30, size: 45 }",
+        ),
+        (
+            ParserConfig::new().max_chunk_extensions_length(5),
+            format!("{}5;foo=barbaz\r\nHello\r\n0\r\n\r\n", chunked),
+            "ChunkExtensionsTooLong { limit: 5, size: 11 }",
+        ),
+        (
+            ParserConfig::new(),
+            format!("{}{}", chunked, "0".repeat(100000)),
+            "ChunkSizeTooLong { limit: 32, size: 100000 }",
+        ),
+        (
+            ParserConfig::new().max_trailer_count(1),
+            format!("{}0\r\nA: 1\r\nB: 2\r\n\r\n", chunked),
+            "TooManyTrailers { limit: 1, count: 2 }",
+        ),
+        (
+            ParserConfig::new().max_body_size(4),
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello".to_owned(),
+            "BodyTooLarge { limit: 4, size: 5 }",
+        ),
+        (
+            ParserConfig::new().max_body_size(4),
+            format!("{}3\r\nHel\r\n3\r\nlo!\r\n0\r\n\r\n", chunked),
+            "BodyTooLarge { limit: 4, size: 6 }",
+        ),
+    ];
+
+    for (config, input, expected) in cases {
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::with_config(ParserType::Request, config);
+        match http_parser.parse_request(&mut cb, input.as_bytes()) {
+            Err(e) => assert_eq!(expected, format!("{:?}", e.kind)),
+            Ok(_) => panic!("Expected {} for {:?}", expected, input),
+        }
+    }
+}
+
+#[test]
+fn test_within_limits() {
+    let config = ParserConfig::new()
+        .max_start_line_length(32)
+        .max_header_line_length(28)
+        .max_header_count(2)
+        .max_headers_size(55)
+        .max_chunk_extensions_length(11)
+        .max_trailer_count(1)
+        .max_body_size(5);
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::with_config(ParserType::Request, config);
+    let input = b"GET /a/very/long/path HTTP/1.1\r\n\
+X-Long-Header: abcdefghij\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+5;foo=barbaz\r\nHello\r\n\
+0\r\n\
+A: 1\r\n\
+\r\n";
+
+    let remaining = http_parser.parse_request(&mut cb, input).unwrap().0;
+    assert_eq!(b"", remaining);
+    assert_eq!(Some(&"end".to_owned()), cb.events.last());
+}
+
+
+#[test]
+fn test_conflicting_framing() {
+    let cases = vec![
+        (
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "TransferEncodingWithContentLength",
+        ),
+        (
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n",
+            "ConflictingContentLength",
+        ),
+        (
+            "POST / HTTP/1.1\r\nContent-Length: 5, 6\r\n\r\n",
+            "ConflictingContentLength",
+        ),
+        (
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+            "ChunkedNotFinal",
+        ),
+        (
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n",
+            "ChunkedNotFinal",
+        ),
+        (
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+            "ChunkedNotFinal",
+        ),
+    ];
+
+    for (input, expected) in cases {
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::new(ParserType::Request);
+        match http_parser.parse_request(&mut cb, input.as_bytes()) {
+            Err(e) => assert_eq!(format!("BadHeaderValue({})", expected), format!("{:?}", e.kind)),
+            Ok(_) => panic!("Expected {} for {:?}", expected, input),
+        }
+    }
+
+    // The error chain leads down to what was wrong with the value.
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let err = http_parser.parse_request(
+        &mut EventRecorder::new(), b"POST / HTTP/1.1\r\nContent-Length: 1x\r\n\r\n"
+    ).unwrap_err();
+    let mut chain = Vec::new();
+    let mut source = ::std::error::Error::source(&err);
+    while let Some(err) = source {
+        chain.push(err.to_string());
+        source = err.source();
+    }
+    assert_eq!(vec![
+        "HttpHeaderParseError: Failed to parse Content-Length: \
+         Could not parse int: Buffer included invalid character '78'.".to_owned(),
+        "Could not parse int: Buffer included invalid character '78'.".to_owned(),
+    ], chain);
+}
+
+#[test]
+fn test_accepted_framing() {
+    let cases = vec![
+        (
+            ParserConfig::new(),
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nHello",
+        ),
+        (
+            ParserConfig::new(),
+            "POST / HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\nHello",
+        ),
+        (
+            ParserConfig::new().allow_conflicting_framing(true),
+            "POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nHello\r\n0\r\n\r\n",
+        ),
+    ];
+
+    for (config, input) in cases {
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::with_config(ParserType::Request, config);
+        http_parser.parse_request(&mut cb, input.as_bytes()).unwrap();
+        assert!(cb.events.contains(&"chunk Hello".to_owned()), "{:?}", cb.events);
+        assert_eq!(Some(&"end".to_owned()), cb.events.last());
+    }
+
+    // Transfer-Encoding wins over Content-Length, but the message is flagged and the
+    // connection can't be reused.
+    let mut cb = EventRecorder::new();
+    cb.record_anomalies = true;
+    let config = ParserConfig::new().allow_conflicting_framing(true);
+    let mut http_parser = HttpParser::with_config(ParserType::Request, config);
+    http_parser.parse_request(
+        &mut cb, b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n"
+    ).unwrap();
+    assert_eq!(vec![
+        "message_begin 0 at 0",
+        "request_line POST /",
+        "header Content-Length: 3",
+        "anomaly TransferEncodingWithContentLength at 36",
+        "header Transfer-Encoding: chunked",
+        "headers_finished",
+    ], cb.events);
+    assert_eq!(BodyType::Chunked, http_parser.body_type);
+    assert!(!http_parser.should_keep_alive());
+}
+
+#[test]
+fn test_response_chunked_not_final() {
+    // Responses where chunked isn't the final coding are read until the connection closes.
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.parse_response(
+        &mut cb, b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked, gzip\r\n\r\nHello"
+    ).unwrap();
+    assert_eq!(BodyType::EOF, http_parser.body_type);
+    assert_eq!(Some(&"chunk Hello".to_owned()), cb.events.last());
+}
+
+
+#[test]
+fn test_transfer_codings() {
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    http_parser.parse_request(
+        &mut cb,
+        b"POST / HTTP/1.1\r\n\
+Transfer-Encoding: gzip\r\n\
+Transfer-Encoding: deflate ,chunked;foo=bar\r\n\
+\r\n\
+5\r\nHello"
+    ).unwrap();
+
+    assert_eq!(BodyType::Chunked, http_parser.body_type);
+    assert_eq!(
+        &[TransferCoding::Gzip, TransferCoding::Deflate, TransferCoding::Chunked][..],
+        http_parser.transfer_codings()
+    );
+    assert_eq!(Some(&"chunk Hello".to_owned()), cb.events.last());
+}
+
+#[test]
+fn test_unrecognized_transfer_coding() {
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let res = http_parser.parse_request(
+        &mut cb, b"POST / HTTP/1.1\r\nTransfer-Encoding: rot13, chunked\r\n\r\n"
+    );
+    match res {
+        Err(HttpParserError {
+            kind: ErrorKind::BadHeaderValue(HttpHeaderParseError::UnrecognizedTransferEncoding), ..
+        }) => {},
+        r => panic!("Expected UnrecognizedTransferEncoding: {:?}", r),
+    }
+}
+
+
+#[test]
+fn test_responses_without_bodies() {
+    let input = b"HTTP/1.1 100 Continue\r\n\
+\r\n\
+HTTP/1.1 200 OK\r\n\
+Content-Length: 5\r\n\
+\r\n\
+HTTP/1.1 204 No Content\r\n\
+Content-Length: 5\r\n\
+\r\n\
+HTTP/1.1 304 Not Modified\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+HTTP/1.1 200 OK\r\n\
+Content-Length: 5\r\n\
+\r\n\
+Hello";
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.push_request_method(Method::Head);
+    http_parser.push_request_method(Method::Get);
+    http_parser.push_request_method(Method::Get);
+    http_parser.push_request_method(Method::Get);
+
+    let mut remaining = &input[..];
+    while remaining.len() > 0 {
+        remaining = http_parser.parse_response(&mut cb, remaining).unwrap().0;
+    }
+
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "response_line 100".to_owned(),
+            "headers_finished".to_owned(),
+            "end".to_owned(),
+            "message_begin 1 at 25".to_owned(),
+            "response_line 200".to_owned(),
+            "header Content-Length: 5".to_owned(),
+            "headers_finished".to_owned(),
+            "end".to_owned(),
+            "message_begin 2 at 63".to_owned(),
+            "response_line 204".to_owned(),
+            "header Content-Length: 5".to_owned(),
+            "headers_finished".to_owned(),
+            "end".to_owned(),
+            "message_begin 3 at 109".to_owned(),
+            "response_line 304".to_owned(),
+            "header Transfer-Encoding: chunked".to_owned(),
+            "headers_finished".to_owned(),
+            "end".to_owned(),
+            "message_begin 4 at 166".to_owned(),
+            "response_line 200".to_owned(),
+            "header Content-Length: 5".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk Hello".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+}
+
+
+#[test]
+fn test_upgrade_response() {
+    let input = b"HTTP/1.1 101 Switching Protocols\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+\r\n\
+\x81\x05Hello";
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.push_request_method(Method::Get);
+
+    let (remaining, status) = http_parser.parse_response(&mut cb, input).unwrap();
+    assert_eq!(ParseStatus::Upgraded, status);
+    assert_eq!(b"\x81\x05Hello", remaining);
+    assert!(http_parser.is_upgraded());
+    assert_eq!(&["end".to_owned(), "upgrade".to_owned()], &cb.events[5..]);
+
+    // Once upgraded the parser leaves everything alone.
+    let remaining = http_parser.parse_response(&mut cb, remaining).unwrap().0;
+    assert_eq!(b"\x81\x05Hello", remaining);
+    assert_eq!(7, cb.events.len());
+}
+
+#[test]
+fn test_upgrade_request() {
+    let input = b"GET /chat HTTP/1.1\r\n\
+Host: example.com\r\n\
+Upgrade: websocket\r\n\
+Connection: Upgrade\r\n\
+\r\n\
+\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let remaining = http_parser.parse_request(&mut cb, input).unwrap().0;
+    assert_eq!(b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58", remaining);
+    assert!(http_parser.is_upgraded());
+    assert!(!http_parser.should_keep_alive());
+    assert_eq!(&["end".to_owned(), "upgrade".to_owned()], &cb.events[6..]);
+
+    // Upgrade needs both the header and the connection option, and isn't allowed in HTTP/1.0.
+    for input in [
+        &b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\n\r\n"[..],
+        b"GET /chat HTTP/1.1\r\nConnection: upgrade\r\n\r\n",
+        b"GET /chat HTTP/1.0\r\nUpgrade: websocket\r\nConnection: upgrade\r\n\r\n",
+    ].iter() {
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::new(ParserType::Request);
+        assert_eq!(b"", http_parser.parse_request(&mut cb, input).unwrap().0);
+        assert!(!http_parser.is_upgraded(), "{:?}", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn test_connect() {
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let remaining = http_parser.parse_request(
+        &mut cb, b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n\x16\x03\x01"
+    ).unwrap().0;
+    assert_eq!(b"\x16\x03\x01", remaining);
+    assert!(http_parser.is_upgraded());
+    assert_eq!(&["end".to_owned(), "upgrade".to_owned()], &cb.events[4..]);
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.push_request_method(Method::Connect);
+    let remaining = http_parser.parse_response(
+        &mut cb, b"HTTP/1.1 200 Connection Established\r\nContent-Length: 10\r\n\r\n\x16\x03\x01"
+    ).unwrap().0;
+    assert_eq!(b"\x16\x03\x01", remaining);
+    assert!(http_parser.is_upgraded());
+    assert_eq!(&["end".to_owned(), "upgrade".to_owned()], &cb.events[4..]);
+
+    // A failed CONNECT is a normal response.
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.push_request_method(Method::Connect);
+    let remaining = http_parser.parse_response(
+        &mut cb, b"HTTP/1.1 403 Forbidden\r\nContent-Length: 2\r\n\r\nNo"
+    ).unwrap().0;
+    assert_eq!(b"", remaining);
+    assert!(!http_parser.is_upgraded());
+    assert_eq!(&["chunk No".to_owned(), "end".to_owned()], &cb.events[4..]);
+}
+
+
+#[test]
+fn test_should_keep_alive() {
+    let requests = vec![
+        ("GET / HTTP/1.1\r\n\r\n", true),
+        ("GET / HTTP/1.1\r\nConnection: close\r\n\r\n", false),
+        ("GET / HTTP/1.1\r\nConnection: Upgrade, close\r\n\r\n", false),
+        ("GET / HTTP/1.1\r\nConnection: foo\r\nConnection: close\r\n\r\n", false),
+        ("GET / HTTP/1.0\r\n\r\n", false),
+        ("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n", true),
+        ("GET / HTTP/1.0\r\nConnection: foo,keep-alive\r\n\r\n", true),
+    ];
+
+    for (input, expected) in requests {
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::new(ParserType::Request);
+        http_parser.parse_request(&mut cb, input.as_bytes()).unwrap();
+        assert_eq!(Some(&"end".to_owned()), cb.events.last());
+        assert_eq!(expected, http_parser.should_keep_alive(), "{:?}", input);
+    }
+
+    let responses = vec![
+        ("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n", true),
+        ("HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n", false),
+        ("HTTP/1.1 200 OK\r\n\r\n", false),
+        ("HTTP/1.1 204 No Content\r\n\r\n", true),
+        ("HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n", false),
+        ("HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nContent-Length: 0\r\n\r\n", true),
+        ("HTTP/1.0 200 OK\r\nConnection: keep-alive\r\n\r\n", false),
+    ];
+
+    for (input, expected) in responses {
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::new(ParserType::Response);
+        http_parser.parse_response(&mut cb, input.as_bytes()).unwrap();
+        http_parser.finish(&mut cb).unwrap();
+        assert_eq!(Some(&"end".to_owned()), cb.events.last());
+        assert_eq!(expected, http_parser.should_keep_alive(), "{:?}", input);
+    }
+}
+
+
+#[test]
+fn test_expect_continue() {
+    let headers = b"PUT /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n";
+
+    // Accepting the body
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let remaining = http_parser.parse_request(&mut cb, headers).unwrap().0;
+    assert_eq!(b"", remaining);
+    assert!(http_parser.expects_continue());
+    assert_eq!(Some(&"headers_finished".to_owned()), cb.events.last());
+
+    http_parser.parse_request(&mut cb, b"Hello").unwrap();
+    assert_eq!(&["chunk Hello".to_owned(), "end".to_owned()], &cb.events[5..]);
+    assert!(http_parser.should_keep_alive());
+    assert!(!http_parser.expects_continue());
+
+    // Rejecting the body without reading it
+    let mut cb = EventRecorder::new();
+    cb.expect_body = ExpectBody::No;
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    http_parser.parse_request(&mut cb, headers).unwrap();
+    assert_eq!(Some(&"end".to_owned()), cb.events.last());
+    assert!(!http_parser.should_keep_alive());
+
+    // Rejecting the body, but discarding it if the client sends it anyway
+    let mut cb = EventRecorder::new();
+    cb.skip_body_on = Some("headers_finished");
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    http_parser.parse_request(&mut cb, headers).unwrap();
+    http_parser.parse_request(&mut cb, b"Hello").unwrap();
+    assert_eq!(Some(&"end".to_owned()), cb.events.last());
+    assert!(!cb.events.contains(&"chunk Hello".to_owned()));
+    assert!(http_parser.should_keep_alive());
+
+    // The expectation is ignored for HTTP/1.0
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    http_parser.parse_request(
+        &mut cb, b"PUT /upload HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n"
+    ).unwrap();
+    assert!(!http_parser.expects_continue());
+}
+
+
+#[test]
+fn test_trailers() {
+    let input = b"HTTP/1.1 200 OK\r\n\
+Transfer-Encoding: chunked\r\n\
+Trailer: Expires, X-Checksum\r\n\
+\r\n\
+5\r\nHello\r\n\
+0\r\n\
+X-Checksum: 1234\r\n\
+Content-Length: 10\r\n\
+\r\n";
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    let remaining = http_parser.parse_response(&mut cb, &input[..input.len() - 2]).unwrap().0;
+    // A Content-Length trailer has no effect on the framing.
+    assert_eq!(BodyType::Chunked, http_parser.body_type);
+    let consumed = input.len() - 2 - remaining.len();
+    assert_eq!(b"", http_parser.parse_response(&mut cb, &input[consumed..]).unwrap().0);
+
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "response_line 200".to_owned(),
+            "header Transfer-Encoding: chunked".to_owned(),
+            "header Trailer: Expires, X-Checksum".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk_header 5".to_owned(),
+            "chunk Hello".to_owned(),
+            "chunk_header 0".to_owned(),
+            "trailer X-Checksum: 1234".to_owned(),
+            "trailer Content-Length: 10".to_owned(),
+            "trailers_finished".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+
+    let config = ParserConfig::new().require_announced_trailers(true);
+    let mut http_parser = HttpParser::with_config(ParserType::Response, config);
+    match http_parser.parse_response(&mut EventRecorder::new(), input) {
+        Err(HttpParserError { kind: ErrorKind::UnannouncedTrailer, .. }) => {},
+        r => panic!("Expected UnannouncedTrailer: {:?}", r),
+    }
+
+    let config = ParserConfig::new().require_announced_trailers(true);
+    let mut http_parser = HttpParser::with_config(ParserType::Response, config);
+    let mut cb = EventRecorder::new();
+    http_parser.parse_response(&mut cb, &input[..input.len() - 21]).unwrap();
+    assert_eq!(Some(&"trailer X-Checksum: 1234".to_owned()), cb.events.last());
+}
+
+
+#[test]
+fn test_message_begin() {
+    let input = b"GET /first HTTP/1.1\r\nContent-Length: 2\r\n\r\nHi\
+GET /second HTTP/1.1\r\n\r\n";
+
+    let mut cb = EventRecorder::new();
+    cb.pause_on = Some("message_begin");
+    let mut http_parser = HttpParser::new(ParserType::Request);
+
+    // Feed the first request in two pieces, so that its first line is incomplete.
+    let remaining = http_parser.parse_request(&mut cb, &input[..5]).unwrap().0;
+    assert!(http_parser.is_paused());
+    assert_eq!(b"GET /", remaining);
+    http_parser.resume();
+    let remaining = http_parser.parse_request(&mut cb, remaining).unwrap().0;
+    assert_eq!(b"GET /", remaining);
+    assert_eq!(0, http_parser.message_sequence());
+    assert_eq!(0, http_parser.message_offset());
+
+    let remaining = http_parser.parse_request(&mut cb, input).unwrap().0;
+    assert_eq!(&input[44..], remaining);
+    let remaining = http_parser.parse_request(&mut cb, remaining).unwrap().0;
+    assert!(http_parser.is_paused());
+    assert_eq!(1, http_parser.message_sequence());
+    assert_eq!(44, http_parser.message_offset());
+    http_parser.resume();
+    let remaining = http_parser.parse_request(&mut cb, remaining).unwrap().0;
+    assert_eq!(b"", remaining);
+
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "request_line GET /first".to_owned(),
+            "header Content-Length: 2".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk Hi".to_owned(),
+            "end".to_owned(),
+            "message_begin 1 at 44".to_owned(),
+            "request_line GET /second".to_owned(),
+            "headers_finished".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+}
+
+
+#[test]
+fn test_callback_error() {
+    let input = b"POST /upload HTTP/1.1\r\n\
+Content-Length: 5\r\n\
+X-Quota: exceeded\r\n\
+\r\n\
+Hello";
+
+    let mut cb = EventRecorder::new();
+    cb.fail_on = Some("header X-Quota");
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    match http_parser.parse_request(&mut cb, input) {
+        Err(HttpParserError { kind: ErrorKind::Callback(err), .. }) => {
+            assert_eq!("rejected by callback", err.to_string());
+        },
+        r => panic!("Expected a callback error: {:?}", r),
+    }
+    assert_eq!(Some(&"header X-Quota: exceeded".to_owned()), cb.events.last());
+
+    let mut cb = EventRecorder::new();
+    cb.fail_on = Some("headers_finished");
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    match http_parser.parse_request(&mut cb, input) {
+        Err(err @ HttpParserError { kind: ErrorKind::Callback(_), .. }) => {
+            let source = ::std::error::Error::source(&err).map(|e| e.to_string());
+            assert_eq!(Some("rejected by callback".to_owned()), source);
+        },
+        r => panic!("Expected a callback error: {:?}", r),
+    }
+    assert_eq!(Some(&"headers_finished".to_owned()), cb.events.last());
+}
+
+
+#[test]
+fn test_null_callbacks() {
+    let input = b"HTTP/1.1 100 Continue\r\n\r\n\
+HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello\
+HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n0\r\n\r\n";
+
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    let mut remaining = &input[..];
+    while remaining.len() > 0 {
+        remaining = http_parser.parse_response(&mut NullCallbacks, remaining).unwrap().0;
+    }
+    assert_eq!(2, http_parser.message_sequence());
+    assert_eq!(68, http_parser.message_offset());
+}
+
+
+#[test]
+fn test_next_event() {
+    let input = b"POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+5\r\nHello\r\n\
+0\r\n\
+X-Checksum: 1234\r\n\
+\r\n\
+GET / HTTP/1.1\r\n\r\n";
+
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let mut events = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    // Feed the input a byte at a time, keeping any input that isn't consumed.
+    loop {
+        let (event, consumed) = http_parser.next_event(&input[start..end]).unwrap();
+        start += consumed;
+        if event != HttpEvent::NeedMore {
+            events.push(event);
+        } else if end < input.len() {
+            end += 1;
+        } else {
+            break;
+        }
+    }
+    assert_eq!(input.len(), start);
+
+    assert_eq!(
+        vec![
+            HttpEvent::RequestLine(RequestLine {
+                method: Method::Post, path: b"/upload", version: (1, 1),
+            }),
+            HttpEvent::Header(b"Transfer-Encoding", b"chunked"),
+            HttpEvent::HeadersComplete(BodyType::Chunked),
+            HttpEvent::ChunkHeader(ChunkHeader { parameters: vec![], size: 5 }),
+            HttpEvent::Body(b"H"),
+            HttpEvent::Body(b"e"),
+            HttpEvent::Body(b"l"),
+            HttpEvent::Body(b"l"),
+            HttpEvent::Body(b"o"),
+            HttpEvent::ChunkHeader(ChunkHeader { parameters: vec![], size: 0 }),
+            HttpEvent::Trailer(b"X-Checksum", b"1234"),
+            HttpEvent::MessageComplete,
+            HttpEvent::RequestLine(RequestLine {
+                method: Method::Get, path: b"/", version: (1, 1),
+            }),
+            HttpEvent::HeadersComplete(BodyType::NoBody),
+            HttpEvent::MessageComplete,
+        ],
+        events
+    );
+}
+
+#[test]
+fn test_finish_event() {
+    // A body delimited by the end of the stream only completes once the stream ends.
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    let input = b"HTTP/1.1 200 OK\r\n\r\nHello";
+    let mut remaining = &input[..];
+    let mut events = Vec::new();
+    loop {
+        let (event, consumed) = http_parser.next_event(remaining).unwrap();
+        remaining = &remaining[consumed..];
+        if event == HttpEvent::NeedMore {
+            break;
+        }
+        events.push(event);
+    }
+    assert_eq!(Some(&HttpEvent::Body(b"Hello")), events.last());
+    assert_eq!(Some(HttpEvent::MessageComplete), http_parser.finish_event().unwrap());
+    assert_eq!(None, http_parser.finish_event().unwrap());
+
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.next_event(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHel").unwrap();
+    match http_parser.finish_event() {
+        Err(HttpParserError { kind: ErrorKind::UnexpectedEof, .. }) => {},
+        r => panic!("Expected an unexpected EOF error: {:?}", r),
+    }
+}
+
+#[test]
+fn test_next_event_recovery() {
+    let input = b"GET /a HTTP/1.1\r\nX: y\n\r\n\
+POST /b HTTP/1.1\r\nBad Header\r\n\r\n\
+GET /c HTTP/1.1\r\n\r\n";
+
+    let config = ParserConfig::new().recover_from_errors(true);
+    let mut http_parser = HttpParser::with_config(ParserType::Request, config);
+    let mut remaining = &input[..];
+    let mut events = Vec::new();
+    loop {
+        let (event, consumed) = http_parser.next_event(remaining).unwrap();
+        remaining = &remaining[consumed..];
+        if event == HttpEvent::NeedMore {
+            break;
+        }
+        events.push(event);
+    }
+    assert_eq!(0, remaining.len());
+    assert_eq!(None, http_parser.finish_event().unwrap());
+
+    // Anomalies come after the event for their line.
+    assert_eq!(
+        vec![
+            HttpEvent::RequestLine(RequestLine {
+                method: Method::Get, path: b"/a", version: (1, 1),
+            }),
+            HttpEvent::Header(b"X", b"y"),
+            HttpEvent::Anomaly(Anomaly::BareLF, 21),
+            HttpEvent::HeadersComplete(BodyType::NoBody),
+            HttpEvent::MessageComplete,
+            HttpEvent::RequestLine(RequestLine {
+                method: Method::Post, path: b"/b", version: (1, 1),
+            }),
+        ],
+        &events[..6]
+    );
+    match events[6] {
+        HttpEvent::Error(HttpParserError {
+            kind: ErrorKind::BadHeader(SyntaxError::MissingColon), offset: 46, ..
+        }) => {},
+        ref e => panic!("Expected a recovered error: {:?}", e),
+    }
+    assert_eq!(
+        vec![
+            HttpEvent::Resync(10),
+            HttpEvent::RequestLine(RequestLine {
+                method: Method::Get, path: b"/c", version: (1, 1),
+            }),
+            HttpEvent::HeadersComplete(BodyType::NoBody),
+            HttpEvent::MessageComplete,
+        ],
+        &events[7..]
+    );
+}
+
+
+#[test]
+fn test_detect_message_type() {
+    let input = b"GET / HTTP/1.1\r\nContent-Length: 2\r\n\r\nHi\
+HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello\
+HEAD / HTTP/1.1\r\n\r\n";
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Both);
+
+    // Not enough to tell whether it's a request or a response.
+    assert_eq!((&b"HT"[..], ParseStatus::NeedMore), http_parser.parse(&mut cb, b"HT").unwrap());
+    assert_eq!(vec!["message_begin 0 at 0".to_owned()], cb.events);
+
+    // So a stream that ends there is still cut short.
+    let mut truncated = HttpParser::new(ParserType::Both);
+    truncated.parse(&mut EventRecorder::new(), b"HTT").unwrap();
+    match truncated.finish(&mut EventRecorder::new()) {
+        Err(HttpParserError { kind: ErrorKind::UnexpectedEof, .. }) => {},
+        r => panic!("Expected UnexpectedEof: {:?}", r),
+    }
+
+    let mut remaining = &input[..];
+    while remaining.len() > 0 {
+        remaining = http_parser.parse(&mut cb, remaining).unwrap().0;
+    }
+
+    assert_eq!(
+        vec![
+            "message_begin 0 at 0".to_owned(),
+            "request_line GET /".to_owned(),
+            "header Content-Length: 2".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk Hi".to_owned(),
+            "end".to_owned(),
+            "message_begin 1 at 39".to_owned(),
+            "response_line 200".to_owned(),
+            "header Content-Length: 5".to_owned(),
+            "headers_finished".to_owned(),
+            "chunk Hello".to_owned(),
+            "end".to_owned(),
+            "message_begin 2 at 82".to_owned(),
+            "request_line HEAD /".to_owned(),
+            "headers_finished".to_owned(),
+            "end".to_owned(),
+        ],
+        cb.events
+    );
+
+    // Responses without headers to say otherwise are delimited by the end of the stream.
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Both);
+    http_parser.parse(&mut cb, b"HTTP/1.0 200 OK\r\n\r\nHello").unwrap();
+    http_parser.finish(&mut cb).unwrap();
+    assert_eq!(Some(&"chunk Hello".to_owned()), cb.events.get(3));
+}
+
+
+#[test]
+fn test_error_position() {
+    let input = b"GET / HTTP/1.1\r\n\r\n\
+POST /upload HTTP/1.1\r\n\
+Host: example.com\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+5\r\nHello\r\n\
+5z\r\nWorld\r\n";
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let remaining = http_parser.parse_request(&mut cb, input).unwrap().0;
+    let err = http_parser.parse_request(&mut cb, remaining).unwrap_err();
+    match err.kind {
+        ErrorKind::BadBodyChunkHeader(SyntaxError::InvalidChunkSize) => {},
+        ref kind => panic!("Expected an invalid chunk size: {:?}", kind),
+    }
+    assert_eq!(101, err.offset);
+    assert_eq!(ParserPhase::ChunkHeader, err.phase);
+    assert_eq!(b"z\r\nWorld\r\n".to_vec(), err.excerpt);
+    assert_eq!(
+        "HttpParserError: Invalid chunked header: invalid chunk size. \
+(at offset 101 in ChunkHeader, near \"z\\r\\nWorld\\r\\n\")",
+        err.to_string()
+    );
+
+    let cases = vec![
+        (
+            "GET / HTTP/1.1\r\nHost: example.com\r\nX-Foo bar\r\n\r\n",
+            ErrorKind::BadHeader(SyntaxError::MissingColon), 41, ParserPhase::Headers,
+        ),
+        (
+            "GET\r\n\r\n",
+            ErrorKind::BadFirstLine(SyntaxError::InvalidMethod), 3, ParserPhase::StartLine,
+        ),
+        (
+            "G(ET / HTTP/1.1\r\n\r\n",
+            ErrorKind::BadFirstLine(SyntaxError::InvalidMethod), 1, ParserPhase::StartLine,
+        ),
+        (
+            "GET / HTTP/1.1\r\nHost: example.com\r\n\r\nGET / HTTP/x.1\r\n\r\n",
+            ErrorKind::BadFirstLine(SyntaxError::InvalidVersion), 43, ParserPhase::StartLine,
+        ),
+    ];
+
+    for (input, expected_kind, expected_offset, expected_phase) in cases {
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::new(ParserType::Request);
+        let mut remaining = input.as_bytes();
+        let err = loop {
+            match http_parser.parse_request(&mut cb, remaining) {
+                Ok((r, _)) if r.len() < remaining.len() => remaining = r,
+                Ok(_) => panic!("Expected an error for {:?}", input),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(format!("{:?}", expected_kind), format!("{:?}", err.kind), "{:?}", input);
+        assert_eq!(expected_offset, err.offset, "{:?}", input);
+        assert_eq!(expected_phase, err.phase, "{:?}", input);
+    }
+
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    http_parser.parse_request(&mut cb, b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nHello").unwrap();
+    let err = http_parser.finish(&mut cb).unwrap_err();
+    assert_eq!(44, err.offset);
+    assert_eq!(ParserPhase::Body, err.phase);
+    assert!(err.excerpt.is_empty());
+}
+
+
+#[test]
+fn test_strictness() {
+    let cases = vec![
+        (
+            "GET  / HTTP/1.1\r\n\r\n",
+            ErrorKind::BadFirstLine(SyntaxError::UnexpectedWhitespace), 4,
+        ),
+        (
+            "GET / HTTP/1.1\nHost: example.com\n\n",
+            ErrorKind::BadFirstLine(SyntaxError::BareLF), 14,
+        ),
+        (
+            "GET / HTTP/1.1\r\nHost : example.com\r\n\r\n",
+            ErrorKind::BadHeader(SyntaxError::WhitespaceBeforeColon), 20,
+        ),
+        (
+            "GET / HTTP/1.1\r\nX-Folded: a\r\n b\r\n\r\n",
+            ErrorKind::BadHeader(SyntaxError::ObsFold), 29,
+        ),
+        (
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\nHello\r\n0\r\n\r\n",
+            ErrorKind::BadBodyChunkHeader(SyntaxError::BareLF), 48,
+        ),
+    ];
+
+    for (input, expected_kind, expected_offset) in cases {
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::new(ParserType::Request);
+        let mut remaining = input.as_bytes();
+        while remaining.len() > 0 {
+            let r = http_parser.parse_request(&mut cb, remaining).unwrap().0;
+            assert!(r.len() < remaining.len(), "No progress for {:?}", input);
+            remaining = r;
+        }
+        assert_eq!(Some(&"end".to_string()), cb.events.last(), "{:?}", input);
+
+        let config = ParserConfig::new().strictness(Strictness::Strict);
+        let mut cb = EventRecorder::new();
+        let mut http_parser = HttpParser::with_config(ParserType::Request, config);
+        let mut remaining = input.as_bytes();
+        let err = loop {
+            match http_parser.parse_request(&mut cb, remaining) {
+                Ok((r, _)) if r.len() < remaining.len() => remaining = r,
+                Ok(_) => panic!("Expected an error for {:?}", input),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(format!("{:?}", expected_kind), format!("{:?}", err.kind), "{:?}", input);
+        assert_eq!(expected_offset, err.offset, "{:?}", input);
+    }
+
+    // Strict mode still takes empty header values and a missing reason phrase.
+    let config = ParserConfig::new().strictness(Strictness::Strict);
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::with_config(ParserType::Response, config);
+    http_parser.parse_response(&mut cb, b"HTTP/1.1 204\r\nX-Empty:\r\n\r\n").unwrap();
+    assert_eq!(vec![
+        "message_begin 0 at 0",
+        "response_line 204",
+        "header X-Empty: ",
+        "headers_finished",
+        "end",
+    ], cb.events);
+}
+
+
+#[test]
+fn test_anomalies() {
+    let input = b"GET  /upload HTTP/1.1\n\
+Host : example.com\r\n\
+X-Folded: first\r\n second\r\n\
+Content-Length: 5\r\n\
+Content-Length: 5\r\n\
+\r\n\
+Hello\
+GET / HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+5\nHello\r\n0\r\n\n";
+
+    let mut cb = EventRecorder::new();
+    cb.record_anomalies = true;
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let mut remaining = &input[..];
+    while remaining.len() > 0 {
+        let r = http_parser.parse_request(&mut cb, remaining).unwrap().0;
+        assert!(r.len() < remaining.len());
+        remaining = r;
+    }
+    let anomalies: Vec<&str> = cb.events.iter()
+        .map(|e| e.as_str())
+        .filter(|e| e.starts_with("anomaly"))
+        .collect();
+    assert_eq!(vec![
+        "anomaly UnexpectedWhitespace at 4",
+        "anomaly BareLF at 21",
+        "anomaly WhitespaceBeforeColon at 26",
+        "anomaly ObsFold at 59",
+        "anomaly DuplicateContentLength at 87",
+        "anomaly BareLF at 160",
+        "anomaly BareLF at 171",
+    ], anomalies);
+
+    // Anomalies are reported before the callback for the line they're on.
+    let header = cb.events.iter().position(|e| e.starts_with("header Host")).unwrap();
+    assert_eq!("anomaly WhitespaceBeforeColon at 26", cb.events[header - 1]);
+
+    let mut cb = EventRecorder::new();
+    cb.record_anomalies = true;
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.parse_response(&mut cb, b"HTTP/1.1 304\r\nContent-Length: 5, 5\r\n\r\n").unwrap();
+    assert_eq!(vec![
+        "message_begin 0 at 0",
+        "anomaly MissingReasonPhrase at 12",
+        "response_line 304",
+        "anomaly DuplicateContentLength at 14",
+        "header Content-Length: 5, 5",
+        "headers_finished",
+        "end",
+    ], cb.events);
+}
+
+
+#[test]
+fn test_error_recovery() {
+    let input = b"GET /a HTTP/1.1\r\n\r\n\
+POST /b HTTP/1.1\r\nBad Header\r\nX: y\r\n\r\n\
+GET /c HTTP/1.1\r\nContent-Length: 2\r\n\r\nOK";
+    let expected = vec![
+        "message_begin 0 at 0",
+        "request_line GET /a",
+        "headers_finished",
+        "end",
+        "message_begin 1 at 19",
+        "request_line POST /b",
+        "error BadHeader(MissingColon) at 41",
+        "resync 16",
+        "message_begin 2 at 57",
+        "request_line GET /c",
+        "header Content-Length: 2",
+        "headers_finished",
+        "chunk OK",
+        "end",
+    ];
+
+    let config = ParserConfig::new().recover_from_errors(true);
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::with_config(ParserType::Request, config.clone());
+    let mut remaining = &input[..];
+    while remaining.len() > 0 {
+        let r = http_parser.parse_request(&mut cb, remaining).unwrap().0;
+        assert!(r.len() < remaining.len());
+        remaining = r;
+    }
+    assert_eq!(expected, cb.events);
+
+    // The same again with the data arriving one byte at a time.
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::with_config(ParserType::Request, config.clone());
+    let mut start = 0;
+    for end in 1..input.len() + 1 {
+        loop {
+            let remaining = http_parser.parse_request(&mut cb, &input[start..end]).unwrap().0;
+            let progress = end - remaining.len() > start;
+            start = end - remaining.len();
+            if !progress {
+                break;
+            }
+        }
+    }
+    let without_body = |events: &[String]| -> Vec<String> {
+        events.iter().filter(|e| !e.starts_with("chunk ")).cloned().collect()
+    };
+    let expected: Vec<String> = expected.iter().map(|e| e.to_string()).collect();
+    assert_eq!(without_body(&expected), without_body(&cb.events));
+
+    let input = b"HTTP/1.1 200 OK\r\nContent-Length: x\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n";
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::with_config(ParserType::Both, config.clone());
+    let remaining = http_parser.parse(&mut cb, input).unwrap().0;
+    let remaining = http_parser.parse(&mut cb, remaining).unwrap().0;
+    assert_eq!(0, remaining.len());
+    assert_eq!(vec![
+        "message_begin 0 at 0",
+        "response_line 200",
+        "header Content-Length: x",
+        "error BadHeaderValue(ContentLength(InvalidChar(120))) at 17",
+        "resync 21",
+        "message_begin 1 at 38",
+        "response_line 204",
+        "headers_finished",
+        "end",
+    ], cb.events);
+
+    // A run of token characters that never ends isn't kept for ever.
+    let config = config.max_start_line_length(64);
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::with_config(ParserType::Request, config.clone());
+    let remaining = http_parser.parse_request(&mut cb, b"G(ET / HTTP/1.1\r\n").unwrap().0;
+    let junk = vec![b'A'; 100000];
+    let (remaining, status) = http_parser.parse_request(&mut cb, &[remaining, &junk[..]].concat())
+        .map(|(r, s)| (r.len(), s)).unwrap();
+    assert_eq!((0, ParseStatus::NeedMore), (remaining, status));
+    http_parser.parse_request(&mut cb, b"\r\nGET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(&["resync 100018".to_owned(), "message_begin 1 at 100019".to_owned()],
+               &cb.events[2..4]);
+
+    // Errors from callbacks still stop the parser.
+    let mut cb = EventRecorder::new();
+    cb.fail_on = Some("request_line");
+    let mut http_parser = HttpParser::with_config(ParserType::Request, config);
+    match http_parser.parse_request(&mut cb, b"GET / HTTP/1.1\r\n\r\n") {
+        Err(HttpParserError { kind: ErrorKind::Callback(_), .. }) => {},
+        r => panic!("Expected a callback error: {:?}", r),
+    }
+}
+
+
+#[test]
+fn test_methods() {
+    let mut cb = EventRecorder::new();
+    let mut http_parser = HttpParser::new(ParserType::Request);
+    let mut requests = Vec::new();
+    let input = b"PROPFIND /dav HTTP/1.1\r\n\r\nX.Y~Z / HTTP/1.1\r\n\r\n";
+    let mut remaining = &input[..];
+    while remaining.len() > 0 {
+        match http_parser.next_event(remaining).unwrap() {
+            (HttpEvent::RequestLine(request), consumed) => {
+                requests.push(request);
+                remaining = &remaining[consumed..];
+            },
+            (_, consumed) => remaining = &remaining[consumed..],
+        }
+    }
+    assert_eq!(vec![Method::Propfind, Method::Extension(b"X.Y~Z")],
+               requests.iter().map(|r| r.method).collect::<Vec<_>>());
+    assert!(requests[0].method.is_safe());
+
+    // A response to HEAD never has a body, whatever its headers say.
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.push_request_method(Method::from_bytes(b"HEAD"));
+    http_parser.parse_response(&mut cb, b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n").unwrap();
+    assert_eq!(Some(&"end".to_owned()), cb.events.last());
+}
+
+
+#[test]
+fn test_status_codes() {
+    // The reason phrase may be left out entirely, space and all.
+    let mut cb = TestResponseHttpCallback::new(ExpectBody::Maybe);
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    http_parser.parse_response(&mut cb, b"HTTP/1.1 204\r\n\r\n").unwrap();
+    assert_eq!(204, cb.code);
+    assert_eq!("", cb.phrase);
+    assert!(cb.finished);
+
+    let mut http_parser = HttpParser::new(ParserType::Response);
+    match http_parser.parse_response(&mut cb, b"HTTP/1.1 000 OK\r\n\r\n") {
+        Err(e) => assert_eq!("BadFirstLine(InvalidStatusCode)", format!("{:?}", e.kind)),
+        Ok(_) => panic!("000 isn't a status code"),
+    }
+}
+
+
 // END TESTS
 
 #[derive(PartialEq,Eq,Debug)]
@@ -288,37 +1730,63 @@ impl TestRequestHttpCallback {
 }
 
 impl <'r> HttpRequestCallbacks<'r> for TestRequestHttpCallback {
-    fn on_request_line(&mut self, _: &mut HttpParser, request: RequestLine) {
+    fn on_request_line(&mut self, _: &mut HttpParser, request: RequestLine) -> CallbackResult<()> {
         println!("on_request_line");
-        self.method = String::from_utf8(request.method.to_owned()).unwrap();
+        self.method = String::from_utf8(request.method.as_bytes().to_owned()).unwrap();
         self.path = String::from_utf8(request.path.to_owned()).unwrap();
         self.version = request.version;
+        Ok(())
     }
 
 }
 
 impl <'r> HttpMessageCallbacks<'r> for TestRequestHttpCallback {
-    fn on_header(&mut self, _: &mut HttpParser, name: &[u8], value: &[u8]) {
+    fn on_message_begin(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
+        println!("on_message_begin");
+        Ok(())
+    }
+    fn on_header(&mut self, _: &mut HttpParser, name: &[u8], value: &[u8]) -> CallbackResult<()> {
         println!(
             "on_header name: {:?}, value: {:?}",
             String::from_utf8_lossy(name),
             String::from_utf8_lossy(value),
         );
         self.headers.insert(String::from_utf8(name.to_owned()).unwrap(), String::from_utf8(value.to_owned()).unwrap());
+        Ok(())
     }
-    fn on_headers_finished(&mut self, _: &mut HttpParser, body_type: BodyType) -> ExpectBody {
+    fn on_headers_finished(&mut self, _: &mut HttpParser, body_type: BodyType)
+    -> CallbackResult<ExpectBody> {
         println!("on_headers_finished");
         println!("BodyType: {:?}", body_type);
-        ExpectBody::Maybe
+        Ok(ExpectBody::Maybe)
     }
-    fn on_chunk(&mut self, _: &mut HttpParser, data: &[u8]) {
+    fn on_chunk_header(&mut self, _: &mut HttpParser, _: ChunkHeader) -> CallbackResult<()> {
+        println!("on_chunk_header");
+        Ok(())
+    }
+    fn on_chunk(&mut self, _: &mut HttpParser, data: &[u8]) -> CallbackResult<()> {
         println!("on_chunk");
 
         self.chunks.push_str(str::from_utf8(data).unwrap());
+        Ok(())
+    }
+    fn on_trailer(&mut self, parser: &mut HttpParser, name: &[u8], value: &[u8])
+    -> CallbackResult<()> {
+        println!("on_trailer");
+        self.on_header(parser, name, value)
+    }
+    fn on_trailers_finished(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
+        println!("on_trailers_finished");
+        Ok(())
     }
-    fn on_end(&mut self, _: &mut HttpParser) {
+    fn on_end(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
         println!("on_end");
         self.finished = true;
+        Ok(())
+    }
+    fn on_upgrade(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
+        println!("on_upgrade");
+        Ok(())
     }
 }
 
@@ -348,36 +1816,193 @@ impl TestResponseHttpCallback {
 }
 
 impl <'r> HttpResponseCallbacks<'r> for TestResponseHttpCallback {
-    fn on_response_line(&mut self, _: &mut HttpParser, response: ResponseLine) {
+    fn on_response_line(&mut self, _: &mut HttpParser, response: ResponseLine)
+    -> CallbackResult<()> {
         println!("on_response_line");
         self.version = response.version;
-        self.code = response.code;
+        self.code = response.code.as_u16();
         self.phrase = String::from_utf8(response.phrase.to_owned()).unwrap();
+        Ok(())
     }
 
 }
 
 impl <'r> HttpMessageCallbacks<'r> for TestResponseHttpCallback {
-    fn on_header(&mut self, _: &mut HttpParser, name: &[u8], value: &[u8]) {
+    fn on_message_begin(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
+        println!("on_message_begin");
+        Ok(())
+    }
+    fn on_header(&mut self, _: &mut HttpParser, name: &[u8], value: &[u8]) -> CallbackResult<()> {
         println!(
             "on_header name: {:?}, value: {:?}",
             String::from_utf8_lossy(name),
             String::from_utf8_lossy(value),
         );
         self.headers.insert(String::from_utf8(name.to_owned()).unwrap(), String::from_utf8(value.to_owned()).unwrap());
+        Ok(())
     }
-    fn on_headers_finished(&mut self, _: &mut HttpParser, body_type: BodyType) -> ExpectBody {
+    fn on_headers_finished(&mut self, _: &mut HttpParser, body_type: BodyType)
+    -> CallbackResult<ExpectBody> {
         println!("on_headers_finished");
         println!("BodyType: {:?}", body_type);
-        self.expect_body
+        Ok(self.expect_body)
+    }
+    fn on_chunk_header(&mut self, _: &mut HttpParser, _: ChunkHeader) -> CallbackResult<()> {
+        println!("on_chunk_header");
+        Ok(())
     }
-    fn on_chunk(&mut self, _: &mut HttpParser, data: &[u8]) {
+    fn on_chunk(&mut self, _: &mut HttpParser, data: &[u8]) -> CallbackResult<()> {
         println!("on_chunk");
 
         self.chunks.push_str(str::from_utf8(data).unwrap());
+        Ok(())
+    }
+    fn on_trailer(&mut self, parser: &mut HttpParser, name: &[u8], value: &[u8])
+    -> CallbackResult<()> {
+        println!("on_trailer");
+        self.on_header(parser, name, value)
+    }
+    fn on_trailers_finished(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
+        println!("on_trailers_finished");
+        Ok(())
     }
-    fn on_end(&mut self, _: &mut HttpParser) {
+    fn on_end(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
         println!("on_end");
         self.finished = true;
+        Ok(())
+    }
+    fn on_upgrade(&mut self, _: &mut HttpParser) -> CallbackResult<()> {
+        println!("on_upgrade");
+        Ok(())
+    }
+}
+
+/// Records every callback as a line of text, so tests can check both what was reported and
+/// the order it was reported in.
+#[derive(PartialEq,Eq,Debug)]
+struct EventRecorder {
+    events: Vec<String>,
+    pause_on: Option<&'static str>,
+    skip_body_on: Option<&'static str>,
+    fail_on: Option<&'static str>,
+    expect_body: ExpectBody,
+    record_anomalies: bool,
+}
+
+impl EventRecorder {
+    fn new() -> EventRecorder {
+        EventRecorder{
+            events: Vec::new(),
+            pause_on: None,
+            skip_body_on: None,
+            fail_on: None,
+            expect_body: ExpectBody::Maybe,
+            record_anomalies: false,
+        }
+    }
+
+    fn record(&mut self, parser: &mut HttpParser, event: String) -> CallbackResult<()> {
+        if let Some(prefix) = self.pause_on {
+            if event.starts_with(prefix) {
+                parser.pause();
+            }
+        }
+        if let Some(prefix) = self.skip_body_on {
+            if event.starts_with(prefix) {
+                parser.skip_body();
+            }
+        }
+        let fail = self.fail_on.map_or(false, |prefix| event.starts_with(prefix));
+        self.events.push(event);
+        if fail {
+            return Err(From::from("rejected by callback"));
+        }
+        Ok(())
+    }
+}
+
+impl <'r> HttpRequestCallbacks<'r> for EventRecorder {
+    fn on_request_line(&mut self, parser: &mut HttpParser, request: RequestLine)
+    -> CallbackResult<()> {
+        self.record(parser, format!(
+            "request_line {} {}",
+            String::from_utf8_lossy(request.method.as_bytes()),
+            String::from_utf8_lossy(request.path),
+        ))
+    }
+}
+
+impl <'r> HttpResponseCallbacks<'r> for EventRecorder {
+    fn on_response_line(&mut self, parser: &mut HttpParser, response: ResponseLine)
+    -> CallbackResult<()> {
+        self.record(parser, format!("response_line {}", response.code))
+    }
+}
+
+impl <'r> HttpMessageCallbacks<'r> for EventRecorder {
+    fn on_message_begin(&mut self, parser: &mut HttpParser) -> CallbackResult<()> {
+        let event = format!(
+            "message_begin {} at {}", parser.message_sequence(), parser.message_offset()
+        );
+        self.record(parser, event)
+    }
+    fn on_header(&mut self, parser: &mut HttpParser, name: &[u8], value: &[u8])
+    -> CallbackResult<()> {
+        self.record(parser, format!(
+            "header {}: {}",
+            String::from_utf8_lossy(name),
+            String::from_utf8_lossy(value),
+        ))
+    }
+    fn on_headers_finished(&mut self, parser: &mut HttpParser, _: BodyType)
+    -> CallbackResult<ExpectBody> {
+        try!(self.record(parser, "headers_finished".to_owned()));
+        Ok(self.expect_body)
+    }
+    fn on_chunk_header(&mut self, parser: &mut HttpParser, header: ChunkHeader)
+    -> CallbackResult<()> {
+        let mut event = format!("chunk_header {}", header.size);
+        for (name, value) in header.parameters {
+            event.push(' ');
+            event.push_str(&String::from_utf8_lossy(name));
+            if let Some(value) = value {
+                event.push('=');
+                event.push_str(&String::from_utf8_lossy(&value));
+            }
+        }
+        self.record(parser, event)
+    }
+    fn on_chunk(&mut self, parser: &mut HttpParser, data: &[u8]) -> CallbackResult<()> {
+        self.record(parser, format!("chunk {}", String::from_utf8_lossy(data)))
+    }
+    fn on_trailer(&mut self, parser: &mut HttpParser, name: &[u8], value: &[u8])
+    -> CallbackResult<()> {
+        self.record(parser, format!(
+            "trailer {}: {}",
+            String::from_utf8_lossy(name),
+            String::from_utf8_lossy(value),
+        ))
+    }
+    fn on_trailers_finished(&mut self, parser: &mut HttpParser) -> CallbackResult<()> {
+        self.record(parser, "trailers_finished".to_owned())
+    }
+    fn on_end(&mut self, parser: &mut HttpParser) -> CallbackResult<()> {
+        self.record(parser, "end".to_owned())
+    }
+    fn on_anomaly(&mut self, parser: &mut HttpParser, kind: Anomaly, offset: u64)
+    -> CallbackResult<()> {
+        if !self.record_anomalies {
+            return Ok(());
+        }
+        self.record(parser, format!("anomaly {:?} at {}", kind, offset))
+    }
+    fn on_error(&mut self, parser: &mut HttpParser, error: HttpParserError) -> CallbackResult<()> {
+        self.record(parser, format!("error {:?} at {}", error.kind, error.offset))
+    }
+    fn on_resync(&mut self, parser: &mut HttpParser, skipped: u64) -> CallbackResult<()> {
+        self.record(parser, format!("resync {}", skipped))
+    }
+    fn on_upgrade(&mut self, parser: &mut HttpParser) -> CallbackResult<()> {
+        self.record(parser, "upgrade".to_owned())
     }
 }